@@ -1,4 +1,17 @@
-use std::{collections::HashMap, env, fmt, fs, mem, time::Instant};
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, BufRead, Write},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process, ptr,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Debug, PartialEq, Clone)]
 enum Meta {
@@ -14,6 +27,29 @@ enum Meta {
     PAR,
     BRK,
     BRC,
+    Arrow,
+    Colon,
+    Equals,
+    Ellipsis,
+}
+
+type NativeFunction = fn(&[Data]) -> Data;
+type NativeMethod = fn(&mut Interpreter, &[Data]) -> Data;
+
+// a builtin written in rust, two of them are equal when they are the same function
+#[derive(Debug, Clone, Copy)]
+struct Native<F>(F);
+
+impl PartialEq for Native<NativeFunction> {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::fn_addr_eq(self.0, other.0)
+    }
+}
+
+impl PartialEq for Native<NativeMethod> {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::fn_addr_eq(self.0, other.0)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -24,23 +60,37 @@ enum Data {
     While,
     Expression,
     KraberFunction {
-        body: fn(&Vec<Data>) -> Data,
+        body: Native<NativeFunction>,
+    },
+    KraberMethod {
+        body: Native<NativeMethod>,
     },
     FunctionContainer {
         params: Vec<String>,
-        param_types: Vec<Data>,
-        return_types: Vec<Data>,
+        param_types: Vec<Node>,
+        return_types: Vec<Node>,
+        defaults: Vec<Option<Node>>,
+        variadic: bool,
     },
     Function {
         params: Vec<String>,
-        param_types: Vec<Data>,
-        return_types: Vec<Data>,
+        param_types: Vec<Node>,
+        return_types: Vec<Node>,
+        defaults: Vec<Option<Node>>,
+        variadic: bool,
         body: Vec<Node>,
+        environment: Environment,
     },
     Return,
     Identifier {
         name: String,
     },
+    Call {
+        name: String,
+    },
+    Argument {
+        name: String,
+    },
     Type {
         name: String,
     },
@@ -64,19 +114,76 @@ enum Data {
         value: Vec<Data>,
         sub_type: Vec<Node>,
     },
+    Map {
+        value: Vec<(Data, Data)>,
+        sub_type: Vec<Node>,
+    },
+    Define,
+    Record {
+        name: String,
+        fields: Vec<(String, Variable)>,
+    },
+    Match,
+    Case {
+        tag: String,
+        bindings: Vec<String>,
+    },
+    Variant {
+        name: String,
+        tag: String,
+        fields: Vec<(String, Variable)>,
+    },
+    Tuple {
+        value: Vec<Data>,
+    },
+    TailCall {
+        function: Box<Data>,
+        args: Vec<Data>,
+        named: Vec<(String, Data)>,
+    },
+    Line {
+        number: usize,
+    },
+    Raise,
+    Try,
+    Catch {
+        name: String,
+    },
+    Finally,
+    Import {
+        path: String,
+        name: String,
+    },
+    Export {
+        name: String,
+    },
 }
 
 fn stringify_enum(data: &Data) -> String {
-    let enum_string = data.clone().to_string();
+    if let Data::Record { name, fields: _ } | Data::Variant { name, .. } = data {
+        return name.to_string();
+    }
+    let enum_string = format!("{:?}", data);
     let collection: Vec<&str> = enum_string.split(' ').collect();
     collection[0].to_string().to_lowercase()
 }
 
+// builtins check how many arguments they received before looking at them
+fn expect_arguments(args: &[Data], count: usize) {
+    if args.len() != count {
+        let noun = if count == 1 { "argument" } else { "arguments" };
+        fail(
+            "arity",
+            format!("expected {count} {noun} but received {}", args.len()),
+        );
+    }
+}
+
 fn expect_type(arg: &Data) -> String {
     return match arg {
         Data::Type { name } => name.to_string(),
         _ => {
-            panic!("expected Data::Type but got {:#?}", arg);
+            fail("type", format!("expected Data::Type but got {:#?}", arg));
         }
     };
 }
@@ -85,7 +192,7 @@ fn expect_boolean(arg: &Data) -> bool {
     return match arg {
         Data::Boolean { value } => *value,
         _ => {
-            panic!("expected Data::Boolean but got {:#?}", arg);
+            fail("type", format!("expected Data::Boolean but got {:#?}", arg));
         }
     };
 }
@@ -96,7 +203,7 @@ fn expect_numeric(arg: &Data) -> f64 {
         Data::Integer { value } => *value as f64,
         Data::Float { value } => *value,
         _ => {
-            panic!("{:#?} is not numeric", arg);
+            fail("type", format!("{:#?} is not numeric", arg));
         }
     };
 }
@@ -105,12 +212,12 @@ fn expect_text(arg: &Data) -> String {
     return match arg {
         Data::Text { value } => value.to_string(),
         _ => {
-            panic!("expected Data::Text but got {:#?}", arg);
+            fail("type", format!("expected Data::Text but got {:#?}", arg));
         }
     };
 }
 
-fn eq(args: &Vec<Data>) -> Data {
+fn eq(args: &[Data]) -> Data {
     Data::Boolean {
         value: args
             .windows(2)
@@ -118,25 +225,21 @@ fn eq(args: &Vec<Data>) -> Data {
     }
 }
 
-fn lt(args: &Vec<Data>) -> Data {
-    if args.len() != 2 {
-        panic!("expected 2 arguments but received {}", args.len());
-    }
+fn lt(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
     Data::Boolean {
         value: expect_numeric(&args[0]) < expect_numeric(&args[1]),
     }
 }
 
-fn nand(args: &Vec<Data>) -> Data {
-    if args.len() != 2 {
-        panic!("expected 2 arguments but received {}", args.len());
-    }
+fn nand(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
     Data::Boolean {
         value: !(expect_boolean(&args[0]) && expect_boolean(&args[1])),
     }
 }
 
-fn add(args: &Vec<Data>) -> Data {
+fn add(args: &[Data]) -> Data {
     let mut sum: f64 = 0.0;
     for arg in args {
         let num: f64 = expect_numeric(arg);
@@ -145,7 +248,7 @@ fn add(args: &Vec<Data>) -> Data {
     Data::Float { value: sum }
 }
 
-fn multiply(args: &Vec<Data>) -> Data {
+fn multiply(args: &[Data]) -> Data {
     let mut text_string = "".to_string();
     let mut product: f64 = 1.0;
     for arg in args {
@@ -170,25 +273,21 @@ fn multiply(args: &Vec<Data>) -> Data {
     }
 }
 
-fn raise(args: &Vec<Data>) -> Data {
-    if args.len() != 2 {
-        panic!("expected 2 arguments but received {}", args.len());
-    }
+fn power(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
     Data::Float {
         value: f64::powf(expect_numeric(&args[0]), expect_numeric(&args[1])),
     }
 }
 
-fn floor(args: &Vec<Data>) -> Data {
-    if args.len() != 1 {
-        panic!("expected 1 argument but received {}", args.len());
-    }
+fn floor(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
     Data::Integer {
         value: expect_numeric(&args[0]) as isize,
     }
 }
 
-fn join(args: &Vec<Data>) -> Data {
+fn join(args: &[Data]) -> Data {
     let mut text_string = "".to_string();
     for arg in args {
         let string = expect_text(&arg);
@@ -203,6 +302,15 @@ fn has_type(nodes: &Vec<Node>, type_node: Node) -> bool {
     for node in nodes {
         if type_node.data == node.data {
             if node.nodes.len() > 0 {
+                if ["map", "tuple", "function"].contains(&expect_type(&node.data).as_str()) {
+                    // map, tuple and function types are positional: `map[key value]`
+                    return type_node.nodes.len() == node.nodes.len()
+                        && node
+                            .nodes
+                            .iter()
+                            .zip(&type_node.nodes)
+                            .all(|(x, y)| has_type(&vec![x.clone()], y.clone()));
+                }
                 if type_node.nodes.len() != 1 || type_node.nodes.len() > node.nodes.len() {
                     return false;
                 }
@@ -214,34 +322,64 @@ fn has_type(nodes: &Vec<Node>, type_node: Node) -> bool {
     false
 }
 
-fn push(args: &Vec<Data>) -> Data {
+fn type_node(data: &Data) -> Node {
+    match data {
+        Data::List { value: _, sub_type } | Data::Map { value: _, sub_type } => Node {
+            id: 0,
+            data: Data::Type {
+                name: stringify_enum(data),
+            },
+            nodes: sub_type.to_vec(),
+        },
+        Data::Tuple { value } => Node {
+            id: 0,
+            data: Data::Type {
+                name: stringify_enum(data),
+            },
+            nodes: value.iter().map(type_node).collect(),
+        },
+        Data::Function {
+            params: _,
+            param_types,
+            return_types,
+            defaults: _,
+            variadic: _,
+            body: _,
+            environment: _,
+        } => Node {
+            id: 0,
+            data: Data::Type {
+                name: stringify_enum(data),
+            },
+            nodes: [
+                tuple_type(0, param_types.to_vec()),
+                tuple_type(1, return_types.to_vec()),
+            ]
+            .to_vec(),
+        },
+        Data::Type { name: _ } => Node {
+            id: 0,
+            data: data.clone(),
+            nodes: [].to_vec(),
+        },
+        _ => Node {
+            id: 0,
+            data: Data::Type {
+                name: stringify_enum(data),
+            },
+            nodes: [].to_vec(),
+        },
+    }
+}
+
+fn push(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
     match &args[0] {
         Data::List { value, sub_type } => {
             let mut x = value.clone();
             dbg!(&args);
-            let n = match &args[1] {
-                Data::List { value, sub_type } => Node {
-                    id: 0,
-                    data: Data::Type {
-                        name: stringify_enum(&args[1]),
-                    },
-                    nodes: sub_type.to_vec(),
-                },
-                Data::Type { name } => Node {
-                    id: 0,
-                    data: args[1].clone(),
-                    nodes: [].to_vec(),
-                },
-                _ => Node {
-                    id: 0,
-                    data: Data::Type {
-                        name: stringify_enum(&args[1]),
-                    },
-                    nodes: [].to_vec(),
-                },
-            };
-            if !has_type(sub_type, n) {
-                panic!("mismatched element types");
+            if !has_type(sub_type, type_node(&args[1])) {
+                fail("type", "mismatched element types".to_string());
             }
             x.push(args[1].clone());
             Data::List {
@@ -250,12 +388,13 @@ fn push(args: &Vec<Data>) -> Data {
             }
         }
         _ => {
-            panic!("sus");
+            fail("type", "sus".to_string());
         }
     }
 }
 
-fn pop(args: &Vec<Data>) -> Data {
+fn pop(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
     match &args[0] {
         Data::List { value, sub_type } => {
             let mut x = value.clone();
@@ -266,212 +405,1429 @@ fn pop(args: &Vec<Data>) -> Data {
             }
         }
         _ => {
-            panic!("sus");
+            fail("type", "sus".to_string());
         }
     }
 }
 
-impl fmt::Display for Data {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+fn length(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let value = match &args[0] {
+        Data::List { value, .. } => value.len(),
+        Data::Map { value, .. } => value.len(),
+        Data::Text { value } => value.chars().count(),
+        _ => {
+            fail(
+                "type",
+                format!("expected a list, map or text but got {:#?}", args[0]),
+            );
+        }
+    };
+    Data::Whole { value }
+}
+
+fn expect_map(arg: &Data) -> (Vec<(Data, Data)>, Vec<Node>) {
+    match arg {
+        Data::Map { value, sub_type } => (value.to_vec(), sub_type.to_vec()),
+        _ => {
+            fail("type", format!("expected Data::Map but got {:#?}", arg));
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-struct Token {
-    value: String,
-    category: Meta,
+fn put_entry(entries: &mut Vec<(Data, Data)>, sub_type: &[Node], key: &Data, value: &Data) {
+    if !has_type(&vec![sub_type[0].clone()], type_node(key)) {
+        fail("type", "mismatched key types".to_string());
+    }
+    if !has_type(&vec![sub_type[1].clone()], type_node(value)) {
+        fail("type", "mismatched value types".to_string());
+    }
+    match entries.iter_mut().find(|entry| entry.0 == *key) {
+        Some(entry) => entry.1 = value.clone(),
+        None => entries.push((key.clone(), value.clone())),
+    }
 }
 
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?} {}", self.category, self.value)
+fn get(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    let (entries, _) = expect_map(&args[0]);
+    match entries.into_iter().find(|entry| entry.0 == args[1]) {
+        Some(entry) => entry.1,
+        None => {
+            fail("index", format!("key {:#?} not found", args[1]));
+        }
     }
 }
 
-struct Lexer {
-    code: String,
-    index: usize,
-    character: char,
-    end: bool,
-    letters: String,
-    digits: String,
-    keywords: Vec<String>,
+fn put(args: &[Data]) -> Data {
+    expect_arguments(args, 3);
+    let (mut entries, sub_type) = expect_map(&args[0]);
+    put_entry(&mut entries, &sub_type, &args[1], &args[2]);
+    Data::Map {
+        value: entries,
+        sub_type,
+    }
 }
 
-impl Lexer {
-    fn step(&mut self) {
-        self.index += 1;
-        if self.index < self.code.len() {
-            self.character = self
-                .code
-                .chars()
-                .nth(self.index)
-                .expect("index is out of range");
-        } else {
-            self.end = true;
+fn assert(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    match (&args[0], &args[1]) {
+        (Data::Boolean { value: true }, _) => Data::Null,
+        (Data::Boolean { value: false }, Data::Text { value }) => {
+            fail("assertion", value.to_string())
+        }
+        _ => {
+            fail("type", "expected a boolean and a text".to_string());
         }
     }
+}
 
-    fn get_word(&mut self) -> String {
-        let mut word: String = "".to_string();
-        while !self.end && self.letters.contains(self.character) {
-            word.push(self.character);
-            self.step();
-        }
-        word
+fn assert_equal(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    if args[0] == args[1] {
+        return Data::Null;
     }
+    let expected = format!("{:#?}", args[0]);
+    let actual = format!("{:#?}", args[1]);
+    fail(
+        "assertion",
+        format!("values are not equal\n{}", diff(&expected, &actual)),
+    )
+}
 
-    fn get_number(&mut self) -> String {
-        let mut number: String = "".to_string();
-        while !self.end && (self.digits.contains(self.character) || self.character == '.') {
-            number.push(self.character);
-            self.step();
+// line diff of two texts, `-` lines are only in the first and `+` lines only in the second
+fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    // longest common subsequence of the lines after every position
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
         }
-        number
     }
-
-    fn get_string(&mut self) -> String {
-        let mut text: String = "".to_string();
-        while !self.end && self.character != '"' {
-            text.push(self.character);
-            self.step();
+    let mut lines: Vec<String> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
         }
-        text
     }
+    lines.join("\n")
+}
 
-    fn get_tokens(&mut self) -> Vec<Token> {
-        let mut tokens: Vec<Token> = Vec::new();
-        let types = [
-            "whole".to_string(),
-            "integer".to_string(),
-            "float".to_string(),
-            "boolean".to_string(),
-            "text".to_string(),
-            "function".to_string(),
-            "list".to_string(),
-        ];
-        while !self.end {
-            if self.letters.contains(self.character) {
-                let word = self.get_word();
-                if self.keywords.contains(&word) {
-                    tokens.push(Token {
-                        value: word,
-                        category: Meta::KEY,
-                    });
-                } else if word == "false" || word == "true" {
-                    tokens.push(Token {
-                        value: word,
-                        category: Meta::BLN,
-                    });
-                } else if types.contains(&word) {
-                    tokens.push(Token {
-                        value: word,
-                        category: Meta::TYP,
-                    });
-                } else if word == "fun" {
-                    tokens.push(Token {
-                        value: word,
-                        category: Meta::FUN,
-                    });
-                } else {
-                    tokens.push(Token {
-                        value: word,
-                        category: Meta::REF,
-                    });
-                }
-                continue;
-            } else if self.digits.contains(self.character)
-                || self.character == '+'
-                || self.character == '-'
-            {
-                let mut number = self.character.to_string();
-                self.step();
-                number.push_str(&self.get_number());
-                if number.contains('.') {
-                    tokens.push(Token {
-                        value: number,
-                        category: Meta::FLT,
-                    });
-                } else if number.contains('+') || number.contains('-') {
-                    tokens.push(Token {
-                        value: number,
-                        category: Meta::INT,
-                    });
-                } else {
-                    tokens.push(Token {
-                        value: number,
-                        category: Meta::WHL,
-                    });
-                }
-                continue;
-            } else if "()".contains(self.character) {
-                tokens.push(Token {
-                    value: self.character.to_string(),
-                    category: Meta::PAR,
-                })
-            } else if "{}".contains(self.character) {
-                tokens.push(Token {
-                    value: self.character.to_string(),
-                    category: Meta::BRC,
-                })
-            } else if "[]".contains(self.character) {
-                tokens.push(Token {
-                    value: self.character.to_string(),
-                    category: Meta::BRK,
-                })
-            } else if self.character == '"' {
-                self.step();
-                let text = self.get_string();
-                tokens.push(Token {
-                    value: text,
-                    category: Meta::TXT,
-                });
-            }
-            self.step();
-        }
-        tokens
+fn is_null(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    Data::Boolean {
+        value: args[0] == Data::Null,
     }
 }
 
-#[derive(Debug, Clone)]
-struct Variable {
-    value: Data,
-    data_type: Vec<Node>,
+fn env_var(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    match env::var(expect_text(&args[0])) {
+        Ok(value) => Data::Text { value },
+        Err(_) => Data::Null,
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-struct Tree {
-    root: Node,
+fn exit(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    panic::panic_any(Exit(expect_numeric(&args[0]) as i32))
 }
 
-#[derive(Debug, PartialEq, Clone)]
-struct Node {
-    id: usize,
-    data: Data,
-    nodes: Vec<Node>,
+const DATE_PATTERN: &str = "%Y-%m-%dT%H:%M:%SZ";
+const DAY: u64 = 24 * 60 * 60 * 1000;
+
+// days since 1970-01-01 of a date in the proleptic gregorian calendar
+fn days_from_date(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
 }
 
-impl Tree {
-    fn new() -> Self {
-        Tree {
-            root: Node {
-                id: 0,
-                data: Data::Main,
-                nodes: Vec::new(),
-            },
+fn date_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+fn date_pattern(args: &[Data]) -> String {
+    match args.len() {
+        1 => DATE_PATTERN.to_string(),
+        2 => expect_text(&args[1]),
+        _ => {
+            fail(
+                "arity",
+                format!("expected 1 or 2 arguments but received {}", args.len()),
+            );
         }
     }
+}
 
-    fn get_scope(&mut self, scope: Vec<usize>) -> &mut Node {
-        self.root.get_scope(scope)
+// dates are in UTC, `%Y %m %d %H %M %S` and `%L` for milliseconds are replaced
+fn format_date(args: &[Data]) -> Data {
+    let pattern = date_pattern(args);
+    let millis = expect_numeric(&args[0]);
+    if millis < 0.0 {
+        fail("type", "cannot format a date before 1970".to_string());
+    }
+    let millis = millis as u64;
+    let (year, month, day) = date_from_days(millis / DAY);
+    let time = millis % DAY;
+    let mut value = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => value += &format!("{year:04}"),
+            Some('m') => value += &format!("{month:02}"),
+            Some('d') => value += &format!("{day:02}"),
+            Some('H') => value += &format!("{:02}", time / 3_600_000),
+            Some('M') => value += &format!("{:02}", time / 60_000 % 60),
+            Some('S') => value += &format!("{:02}", time / 1000 % 60),
+            Some('L') => value += &format!("{:03}", time % 1000),
+            Some('%') => value.push('%'),
+            other => fail(
+                "type",
+                format!(
+                    "unknown date field %{}",
+                    other.map_or(String::new(), String::from)
+                ),
+            ),
+        }
     }
+    Data::Text { value }
 }
 
-impl Node {
-    fn insert(&mut self, item: &Data) -> &mut Node {
-        let mut node = self;
-
+fn parse_date(args: &[Data]) -> Data {
+    let pattern = date_pattern(args);
+    let text = expect_text(&args[0]);
+    let invalid = || -> ! { fail("parse", format!("{text:?} does not match {pattern:?}")) };
+    let mut fields = [1970, 1, 1, 0, 0, 0, 0];
+    let mut rest = text.as_str();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '%' => chars.next(),
+            _ => None,
+        };
+        let (field, width) = match literal {
+            Some('Y') => (0, 4),
+            Some('m') => (1, 2),
+            Some('d') => (2, 2),
+            Some('H') => (3, 2),
+            Some('M') => (4, 2),
+            Some('S') => (5, 2),
+            Some('L') => (6, 3),
+            _ => {
+                let expected = match (c, literal) {
+                    ('%', Some('%')) => '%',
+                    ('%', _) => fail("type", "unknown date field in pattern".to_string()),
+                    _ => c,
+                };
+                rest = rest.strip_prefix(expected).unwrap_or_else(|| invalid());
+                continue;
+            }
+        };
+        let digits = rest.get(..width).unwrap_or_else(|| invalid());
+        if !digits.chars().all(|x| x.is_ascii_digit()) {
+            invalid();
+        }
+        fields[field] = digits.parse().unwrap_or_else(|_| invalid());
+        rest = &rest[width..];
+    }
+    let [year, month, day, hour, minute, second, millis] = fields;
+    if !rest.is_empty() || year < 1970 || !(1..=12).contains(&month) || day == 0 {
+        invalid();
+    }
+    let days = days_from_date(year, month, day);
+    // a day past the end of the month would roll over into the next one
+    if date_from_days(days) != (year, month, day) || hour > 23 || minute > 59 || second > 59 {
+        invalid();
+    }
+    Data::Whole {
+        value: (days * DAY + ((hour * 60 + minute) * 60 + second) * 1000 + millis) as usize,
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+    column: usize,
+}
+
+impl JsonParser {
+    fn fail(&self, message: &str) -> ! {
+        fail(
+            "parse",
+            format!("{message} at line {} column {}", self.line, self.column),
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, word: &str) {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                self.fail(&format!("expected {word}"));
+            }
+            self.next();
+        }
+    }
+
+    fn value(&mut self) -> Data {
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Data::Text { value: self.text() },
+            Some('t') => {
+                self.expect("true");
+                Data::Boolean { value: true }
+            }
+            Some('f') => {
+                self.expect("false");
+                Data::Boolean { value: false }
+            }
+            Some('n') => {
+                self.expect("null");
+                Data::Null
+            }
+            Some('-' | '0'..='9') => self.number(),
+            Some(c) => self.fail(&format!("unexpected {c:?}")),
+            None => self.fail("unexpected end of input"),
+        };
+        self.skip_whitespace();
+        value
+    }
+
+    // -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Data {
+        let start = self.position;
+        let mut fraction = false;
+        if self.peek() == Some('-') {
+            self.next();
+        }
+        if self.peek() == Some('0') {
+            self.next();
+            if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.fail("leading zeros are not allowed");
+            }
+        } else {
+            self.digits();
+        }
+        if self.peek() == Some('.') {
+            self.next();
+            self.digits();
+            fraction = true;
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.next();
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.next();
+            }
+            self.digits();
+            fraction = true;
+        }
+        let number: String = self.chars[start..self.position].iter().collect();
+        if !fraction {
+            if let Ok(value) = number.parse() {
+                return Data::Whole { value };
+            }
+            if let Ok(value) = number.parse() {
+                return Data::Integer { value };
+            }
+        }
+        match number.parse() {
+            Ok(value) => Data::Float { value },
+            Err(_) => self.fail(&format!("invalid number {number}")),
+        }
+    }
+
+    fn digits(&mut self) {
+        if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.fail("expected a digit");
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.next();
+        }
+    }
+
+    fn text(&mut self) -> String {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return value,
+                Some('\\') => match self.next() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('/') => value.push('/'),
+                    Some('b') => value.push('\u{8}'),
+                    Some('f') => value.push('\u{c}'),
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('u') => {
+                        let mut code = self.hex();
+                        // characters outside the basic plane are written as two surrogates
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect("\\u");
+                            let low = self.hex();
+                            if !(0xdc00..0xe000).contains(&low) {
+                                self.fail("invalid unicode escape");
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        match char::from_u32(code) {
+                            Some(c) => value.push(c),
+                            None => self.fail("invalid unicode escape"),
+                        }
+                    }
+                    _ => self.fail("invalid escape"),
+                },
+                Some(c) if (c as u32) < 0x20 => self.fail("control character in string"),
+                Some(c) => value.push(c),
+                None => self.fail("unterminated string"),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> u32 {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.next().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => self.fail("invalid unicode escape"),
+            }
+        }
+        code
+    }
+
+    fn array(&mut self) -> Data {
+        self.next();
+        self.skip_whitespace();
+        let mut value = Vec::new();
+        if self.peek() == Some(']') {
+            self.next();
+        } else {
+            loop {
+                value.push(self.value());
+                match self.peek() {
+                    Some(',') => {
+                        self.next();
+                    }
+                    Some(']') => {
+                        self.next();
+                        break;
+                    }
+                    _ => self.fail("expected , or ]"),
+                }
+            }
+        }
+        // a list when every element has the same type, otherwise a tuple
+        match json_type(&mut value) {
+            Some(sub_type) => Data::List {
+                value,
+                sub_type: [sub_type].to_vec(),
+            },
+            None => Data::Tuple { value },
+        }
+    }
+
+    fn object(&mut self) -> Data {
+        self.next();
+        self.skip_whitespace();
+        let mut fields: Vec<(String, Data)> = Vec::new();
+        if self.peek() == Some('}') {
+            self.next();
+        } else {
+            loop {
+                self.skip_whitespace();
+                if self.peek() != Some('"') {
+                    self.fail("expected a string key");
+                }
+                let key = self.text();
+                self.skip_whitespace();
+                self.expect(":");
+                let value = self.value();
+                match fields.iter_mut().find(|x| x.0 == key) {
+                    Some(field) => field.1 = value,
+                    None => fields.push((key, value)),
+                }
+                match self.peek() {
+                    Some(',') => {
+                        self.next();
+                    }
+                    Some('}') => {
+                        self.next();
+                        break;
+                    }
+                    _ => self.fail("expected , or }"),
+                }
+            }
+        }
+        // always a record, so the shape of the values does not change the type
+        Data::Record {
+            name: "json".to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(field, value)| {
+                    let data_type = [type_node(&value)].to_vec();
+                    (field, Variable { value, data_type })
+                })
+                .collect(),
+        }
+    }
+}
+
+// the shared type of some json values, numbers are widened to the widest of them
+fn json_type(values: &mut [Data]) -> Option<Node> {
+    let present = || values.iter().filter(|x| **x != Data::Null);
+    let numeric = |x: &Data| {
+        matches!(
+            x,
+            Data::Whole { .. } | Data::Integer { .. } | Data::Float { .. }
+        )
+    };
+    let widest = if present().all(numeric) {
+        present()
+            .map(|x| match x {
+                Data::Whole { .. } => 0,
+                Data::Integer { .. } => 1,
+                _ => 2,
+            })
+            .max()
+    } else {
+        None
+    };
+    for value in values.iter_mut() {
+        *value = match (widest, &*value) {
+            (Some(1), Data::Whole { value }) => Data::Integer {
+                value: *value as isize,
+            },
+            (Some(2), Data::Whole { value }) => Data::Float {
+                value: *value as f64,
+            },
+            (Some(2), Data::Integer { value }) => Data::Float {
+                value: *value as f64,
+            },
+            _ => continue,
+        };
+    }
+    let mut types = values.iter().filter(|x| **x != Data::Null).map(type_node);
+    let first = types.next().unwrap_or(type_node(&Data::Type {
+        name: "null".to_string(),
+    }));
+    match types.all(|x| x == first) {
+        true => Some(first),
+        false => None,
+    }
+}
+
+fn parse_json(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let mut parser = JsonParser {
+        chars: expect_text(&args[0]).chars().collect(),
+        position: 0,
+        line: 1,
+        column: 1,
+    };
+    let value = parser.value();
+    if parser.peek().is_some() {
+        parser.fail("unexpected text after the value");
+    }
+    value
+}
+
+fn json(data: &Data) -> String {
+    let object = |fields: Vec<(String, String)>| {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("{}:{value}", json_text(key)))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    };
+    let array = |values: &[Data]| {
+        format!(
+            "[{}]",
+            values.iter().map(json).collect::<Vec<_>>().join(",")
+        )
+    };
+    match data {
+        Data::Null => "null".to_string(),
+        Data::Whole { value } => value.to_string(),
+        Data::Integer { value } => value.to_string(),
+        // debug formatting keeps the fraction of whole valued floats, so 1.0 stays a float
+        Data::Float { value } if value.is_finite() => format!("{value:?}"),
+        Data::Float { value } => fail("type", format!("{value} cannot be written as json")),
+        Data::Boolean { value } => value.to_string(),
+        Data::Text { value } => json_text(value),
+        Data::List { value, .. } | Data::Tuple { value } => array(value),
+        Data::Map { value, .. } => object(
+            value
+                .iter()
+                .map(|(key, value)| match key {
+                    // json keys are always strings
+                    Data::Text { .. }
+                    | Data::Whole { .. }
+                    | Data::Integer { .. }
+                    | Data::Float { .. }
+                    | Data::Boolean { .. } => (key.to_string(), json(value)),
+                    _ => fail("type", format!("{} cannot be a json key", quote(key))),
+                })
+                .collect(),
+        ),
+        Data::Record { fields, .. } => object(
+            fields
+                .iter()
+                .map(|(field, variable)| (field.to_string(), json(&variable.value)))
+                .collect(),
+        ),
+        _ => fail("type", format!("{data} cannot be written as json")),
+    }
+}
+
+fn json_text(value: &str) -> String {
+    let mut text = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => text += "\\\"",
+            '\\' => text += "\\\\",
+            '\n' => text += "\\n",
+            '\r' => text += "\\r",
+            '\t' => text += "\\t",
+            c if (c as u32) < 0x20 => text += &format!("\\u{:04x}", c as u32),
+            c => text.push(c),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn to_json(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    Data::Text {
+        value: json(&args[0]),
+    }
+}
+
+fn has(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    let (entries, _) = expect_map(&args[0]);
+    Data::Boolean {
+        value: entries.iter().any(|entry| entry.0 == args[1]),
+    }
+}
+
+fn keys(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let (entries, sub_type) = expect_map(&args[0]);
+    Data::List {
+        value: entries.into_iter().map(|entry| entry.0).collect(),
+        sub_type: [sub_type[0].clone()].to_vec(),
+    }
+}
+
+fn values(args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let (entries, sub_type) = expect_map(&args[0]);
+    Data::List {
+        value: entries.into_iter().map(|entry| entry.1).collect(),
+        sub_type: [sub_type[1].clone()].to_vec(),
+    }
+}
+
+fn delete(args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    let (mut entries, sub_type) = expect_map(&args[0]);
+    entries.retain(|entry| entry.0 != args[1]);
+    Data::Map {
+        value: entries,
+        sub_type,
+    }
+}
+
+fn is_callable(data: &Data) -> bool {
+    matches!(
+        data,
+        Data::KraberFunction { body: _ }
+            | Data::KraberMethod { body: _ }
+            | Data::Function { .. }
+            | Data::Variant { .. }
+    )
+}
+
+fn map(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    let (value, sub_type) = match &args[0] {
+        Data::List { value, sub_type } => (value.to_vec(), sub_type.to_vec()),
+        _ => {
+            fail(
+                "type",
+                format!("expected Data::List but got {:#?}", args[0]),
+            );
+        }
+    };
+    if !is_callable(&args[1]) {
+        fail(
+            "type",
+            format!("expected a function but got {:#?}", args[1]),
+        );
+    }
+    let value: Vec<Data> = value
+        .into_iter()
+        .map(|x| interpreter.call_function(args[1].clone(), [x].to_vec(), Vec::new()))
+        .collect();
+    let sub_type = match &args[1] {
+        Data::Function { return_types, .. } => return_type_nodes(return_types),
+        _ => match value.first() {
+            Some(x) => [type_node(x)].to_vec(),
+            None => sub_type,
+        },
+    };
+    Data::List { value, sub_type }
+}
+
+fn print(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    interpreter.write(&args.iter().map(|x| x.to_string()).collect::<String>());
+    Data::Null
+}
+
+fn print_line(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    interpreter.write_line(&args.iter().map(|x| x.to_string()).collect::<String>());
+    Data::Null
+}
+
+fn read_line(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 0);
+    let mut line = String::new();
+    if interpreter
+        .input
+        .borrow_mut()
+        .read_line(&mut line)
+        .expect("could not read input")
+        == 0
+    {
+        return Data::Null;
+    }
+    Data::Text {
+        value: line
+            .strip_suffix('\n')
+            .unwrap_or(&line)
+            .trim_end_matches('\r')
+            .to_string(),
+    }
+}
+
+fn read_all(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 0);
+    let mut text = String::new();
+    interpreter
+        .input
+        .borrow_mut()
+        .read_to_string(&mut text)
+        .expect("could not read input");
+    match text.is_empty() {
+        true => Data::Null,
+        false => Data::Text { value: text },
+    }
+}
+
+fn read_lines(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    match read_all(interpreter, args) {
+        Data::Text { value } => Data::List {
+            value: value
+                .lines()
+                .map(|line| Data::Text {
+                    value: line.to_string(),
+                })
+                .collect(),
+            sub_type: new_node_vec(Data::Type {
+                name: "text".to_string(),
+            }),
+        },
+        _ => Data::Null,
+    }
+}
+
+fn expect_path(interpreter: &Interpreter, args: &[Data], count: usize, write: bool) -> PathBuf {
+    expect_arguments(args, count);
+    interpreter
+        .capabilities
+        .check(&expect_text(&args[0]), write)
+}
+
+fn io_error(args: &[Data], error: io::Error) -> ! {
+    fail("io", format!("{}: {error}", expect_text(&args[0])))
+}
+
+fn read_file(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    let path = expect_path(interpreter, args, 1, false);
+    match fs::read_to_string(path) {
+        Ok(value) => Data::Text { value },
+        Err(error) => io_error(args, error),
+    }
+}
+
+fn write_file(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    let path = expect_path(interpreter, args, 2, true);
+    if let Err(error) = fs::write(path, expect_text(&args[1])) {
+        io_error(args, error);
+    }
+    Data::Null
+}
+
+fn append_file(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    let path = expect_path(interpreter, args, 2, true);
+    if let Err(error) = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(expect_text(&args[1]).as_bytes()))
+    {
+        io_error(args, error);
+    }
+    Data::Null
+}
+
+fn list_dir(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    let path = expect_path(interpreter, args, 1, false);
+    let mut names: Vec<String> = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(error) => io_error(args, error),
+    };
+    names.sort();
+    Data::List {
+        value: names
+            .into_iter()
+            .map(|value| Data::Text { value })
+            .collect(),
+        sub_type: new_node_vec(Data::Type {
+            name: "text".to_string(),
+        }),
+    }
+}
+
+fn exists(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    let path = expect_path(interpreter, args, 1, false);
+    Data::Boolean {
+        value: path.exists(),
+    }
+}
+
+fn delete_file(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    // a link is removed itself rather than the file it points to
+    let path = interpreter
+        .capabilities
+        .check_entry(&expect_text(&args[0]), true);
+    if let Err(error) = fs::remove_file(path) {
+        io_error(args, error);
+    }
+    Data::Null
+}
+
+fn debug(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    for arg in args {
+        interpreter.write_line(&format!("{:#?}", arg));
+    }
+    Data::Null
+}
+
+fn seed(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    interpreter.set_seed(expect_numeric(&args[0]) as u64);
+    Data::Null
+}
+
+fn random_float(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 0);
+    // the top 53 bits fill the mantissa of a float in `[0, 1)`
+    Data::Float {
+        value: (interpreter.next_random() >> 11) as f64 / (1u64 << 53) as f64,
+    }
+}
+
+fn random_integer(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    let low = expect_numeric(&args[0]) as isize;
+    let high = expect_numeric(&args[1]) as isize;
+    if high < low {
+        fail("index", format!("empty range from {low} to {high}"));
+    }
+    // both ends are included
+    let span = (high.abs_diff(low) as u64).wrapping_add(1);
+    let offset = match span {
+        0 => interpreter.next_random(), // the range covers every integer
+        _ => interpreter.random_below(span),
+    };
+    Data::Integer {
+        value: low.wrapping_add(offset as isize),
+    }
+}
+
+fn shuffle(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let (mut value, sub_type) = match &args[0] {
+        Data::List { value, sub_type } => (value.to_vec(), sub_type.to_vec()),
+        _ => {
+            fail(
+                "type",
+                format!("expected Data::List but got {:#?}", args[0]),
+            );
+        }
+    };
+    // Fisher-Yates
+    for i in (1..value.len()).rev() {
+        let j = interpreter.random_below(i as u64 + 1) as usize;
+        value.swap(i, j);
+    }
+    Data::List { value, sub_type }
+}
+
+fn choose(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let value = match &args[0] {
+        Data::List { value, .. } => value,
+        _ => {
+            fail(
+                "type",
+                format!("expected Data::List but got {:#?}", args[0]),
+            );
+        }
+    };
+    if value.is_empty() {
+        fail("index", "cannot choose from an empty list".to_string());
+    }
+    value[interpreter.random_below(value.len() as u64) as usize].clone()
+}
+
+fn now(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 0);
+    Data::Whole {
+        value: interpreter.clock.now() as usize,
+    }
+}
+
+fn monotonic(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 0);
+    Data::Whole {
+        value: interpreter.clock.monotonic() as usize,
+    }
+}
+
+fn sleep(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 1);
+    let millis = expect_numeric(&args[0]);
+    if millis < 0.0 {
+        fail("type", "cannot sleep for a negative time".to_string());
+    }
+    interpreter.clock.sleep(millis as u64);
+    Data::Null
+}
+
+fn filter(interpreter: &mut Interpreter, args: &[Data]) -> Data {
+    expect_arguments(args, 2);
+    let (value, sub_type) = match &args[0] {
+        Data::List { value, sub_type } => (value.to_vec(), sub_type.to_vec()),
+        _ => {
+            fail(
+                "type",
+                format!("expected Data::List but got {:#?}", args[0]),
+            );
+        }
+    };
+    if !is_callable(&args[1]) {
+        fail(
+            "type",
+            format!("expected a function but got {:#?}", args[1]),
+        );
+    }
+    let value: Vec<Data> = value
+        .into_iter()
+        .filter(|x| {
+            expect_boolean(&interpreter.call_function(
+                args[1].clone(),
+                [x.clone()].to_vec(),
+                Vec::new(),
+            ))
+        })
+        .collect();
+    Data::List { value, sub_type }
+}
+
+// type as it is written in kraber source
+fn type_name(node: &Node) -> String {
+    let names = |nodes: &Vec<Node>| nodes.iter().map(type_name).collect::<Vec<_>>().join(" ");
+    match &node.data {
+        Data::Type { name } if name == "function" && node.nodes.len() == 2 => {
+            let params = names(&node.nodes[0].nodes);
+            let returns = names(&node.nodes[1].nodes);
+            match params.is_empty() {
+                true => format!("function[-> {returns}]"),
+                false => format!("function[{params} -> {returns}]"),
+            }
+        }
+        Data::Type { name } if !node.nodes.is_empty() => format!("{name}[{}]", names(&node.nodes)),
+        data => data.to_string(),
+    }
+}
+
+// text inside of other values is quoted to tell it apart from the surrounding punctuation
+fn quote(data: &Data) -> String {
+    match data {
+        Data::Text { value } => format!("{value:?}"),
+        _ => data.to_string(),
+    }
+}
+
+fn quote_all(values: &[Data]) -> String {
+    values.iter().map(quote).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Data::Null => write!(f, "null"),
+            Data::Whole { value } => write!(f, "{value}"),
+            Data::Integer { value } => write!(f, "{value}"),
+            Data::Float { value } => write!(f, "{value}"),
+            Data::Boolean { value } => write!(f, "{value}"),
+            Data::Text { value } => write!(f, "{value}"),
+            Data::Type { name } => write!(f, "{name}"),
+            Data::List { value, .. } => write!(f, "[{}]", quote_all(value)),
+            Data::Tuple { value } => write!(f, "({})", quote_all(value)),
+            Data::Map { value, .. } => {
+                let entries: Vec<String> = value
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", quote(key), quote(value)))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Data::Record { name, fields } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, variable)| format!("{field}: {}", quote(&variable.value)))
+                    .collect();
+                write!(f, "{name} {{{}}}", fields.join(", "))
+            }
+            Data::Variant { tag, fields, .. } => {
+                if fields.is_empty() {
+                    return write!(f, "{tag}");
+                }
+                let values: Vec<Data> = fields.iter().map(|x| x.1.value.clone()).collect();
+                write!(f, "{tag}({})", quote_all(&values))
+            }
+            Data::Function {
+                params,
+                param_types,
+                return_types,
+                variadic,
+                ..
+            } => {
+                let mut params: Vec<String> = params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| match param_types.get(i) {
+                        Some(param_type) => format!("{param}: {}", type_name(param_type)),
+                        None => param.to_string(),
+                    })
+                    .collect();
+                if *variadic {
+                    params.last_mut().unwrap().push_str("...");
+                }
+                let returns: Vec<String> = return_types.iter().map(type_name).collect();
+                match returns.len() {
+                    1 => write!(f, "<function({}) -> {}>", params.join(", "), returns[0]),
+                    _ => write!(
+                        f,
+                        "<function({}) -> ({})>",
+                        params.join(", "),
+                        returns.join(", ")
+                    ),
+                }
+            }
+            Data::KraberFunction { .. } | Data::KraberMethod { .. } => {
+                write!(f, "<builtin function>")
+            }
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    value: String,
+    category: Meta,
+    line: usize,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {}", self.category, self.value)
+    }
+}
+
+struct Lexer {
+    code: String,
+    index: usize,
+    line: usize,
+    character: char,
+    end: bool,
+    letters: String,
+    digits: String,
+    keywords: Vec<String>,
+}
+
+impl Lexer {
+    fn step(&mut self) {
+        if self.character == '\n' {
+            self.line += 1;
+        }
+        self.index += 1;
+        if self.index < self.code.len() {
+            self.character = self
+                .code
+                .chars()
+                .nth(self.index)
+                .expect("index is out of range");
+        } else {
+            self.end = true;
+        }
+    }
+
+    fn get_word(&mut self) -> String {
+        let mut word: String = "".to_string();
+        while !self.end
+            && (self.letters.contains(self.character)
+                || (self.character == '.'
+                    && !word.is_empty()
+                    && self
+                        .code
+                        .chars()
+                        .nth(self.index + 1)
+                        .is_some_and(|next| self.letters.contains(next))))
+        {
+            word.push(self.character);
+            self.step();
+        }
+        word
+    }
+
+    fn get_number(&mut self) -> String {
+        let mut number: String = "".to_string();
+        while !self.end && (self.digits.contains(self.character) || self.character == '.') {
+            number.push(self.character);
+            self.step();
+        }
+        number
+    }
+
+    fn get_string(&mut self) -> String {
+        let mut text: String = "".to_string();
+        while !self.end && self.character != '"' {
+            text.push(self.character);
+            self.step();
+        }
+        text
+    }
+
+    fn get_tokens(&mut self) -> Vec<Token> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let types = [
+            "whole".to_string(),
+            "integer".to_string(),
+            "float".to_string(),
+            "boolean".to_string(),
+            "text".to_string(),
+            "function".to_string(),
+            "list".to_string(),
+            "map".to_string(),
+            "record".to_string(),
+            "enum".to_string(),
+            "tuple".to_string(),
+        ];
+        while !self.end {
+            let line = self.line;
+            if self.letters.contains(self.character) {
+                let word = self.get_word();
+                if self.keywords.contains(&word) {
+                    tokens.push(Token {
+                        value: word,
+                        category: Meta::KEY,
+                        line,
+                    });
+                } else if word == "false" || word == "true" {
+                    tokens.push(Token {
+                        value: word,
+                        category: Meta::BLN,
+                        line,
+                    });
+                } else if types.contains(&word) {
+                    tokens.push(Token {
+                        value: word,
+                        category: Meta::TYP,
+                        line,
+                    });
+                } else if word == "fun" {
+                    tokens.push(Token {
+                        value: word,
+                        category: Meta::FUN,
+                        line,
+                    });
+                } else {
+                    tokens.push(Token {
+                        value: word,
+                        category: Meta::REF,
+                        line,
+                    });
+                }
+                continue;
+            } else if self.character == '-' && self.code.chars().nth(self.index + 1) == Some('>') {
+                self.step();
+                tokens.push(Token {
+                    value: "->".to_string(),
+                    category: Meta::Arrow,
+                    line,
+                });
+            } else if self.digits.contains(self.character)
+                || self.character == '+'
+                || self.character == '-'
+            {
+                let mut number = self.character.to_string();
+                self.step();
+                number.push_str(&self.get_number());
+                if number.contains('.') {
+                    tokens.push(Token {
+                        value: number,
+                        category: Meta::FLT,
+                        line,
+                    });
+                } else if number.contains('+') || number.contains('-') {
+                    tokens.push(Token {
+                        value: number,
+                        category: Meta::INT,
+                        line,
+                    });
+                } else {
+                    tokens.push(Token {
+                        value: number,
+                        category: Meta::WHL,
+                        line,
+                    });
+                }
+                continue;
+            } else if "()".contains(self.character) {
+                tokens.push(Token {
+                    value: self.character.to_string(),
+                    category: Meta::PAR,
+                    line,
+                })
+            } else if "{}".contains(self.character) {
+                tokens.push(Token {
+                    value: self.character.to_string(),
+                    category: Meta::BRC,
+                    line,
+                })
+            } else if "[]".contains(self.character) {
+                tokens.push(Token {
+                    value: self.character.to_string(),
+                    category: Meta::BRK,
+                    line,
+                })
+            } else if self.code.chars().skip(self.index).take(3).eq("...".chars()) {
+                self.step();
+                self.step();
+                tokens.push(Token {
+                    value: "...".to_string(),
+                    category: Meta::Ellipsis,
+                    line,
+                })
+            } else if self.character == ':' {
+                tokens.push(Token {
+                    value: self.character.to_string(),
+                    category: Meta::Colon,
+                    line,
+                })
+            } else if self.character == '=' {
+                tokens.push(Token {
+                    value: self.character.to_string(),
+                    category: Meta::Equals,
+                    line,
+                })
+            } else if self.character == '"' {
+                self.step();
+                let text = self.get_string();
+                tokens.push(Token {
+                    value: text,
+                    category: Meta::TXT,
+                    line,
+                });
+            }
+            self.step();
+        }
+        tokens
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Variable {
+    value: Data,
+    data_type: Vec<Node>,
+}
+
+#[derive(Debug)]
+struct Scope {
+    variables: HashMap<String, Variable>,
+    parent: Option<Environment>,
+}
+
+// shared so that closures observe and update the scopes they were created in
+#[derive(Clone)]
+struct Environment(Rc<RefCell<Scope>>);
+
+impl Environment {
+    fn new(parent: Option<Environment>) -> Self {
+        Environment(Rc::new(RefCell::new(Scope {
+            variables: HashMap::new(),
+            parent,
+        })))
+    }
+
+    fn get(&self, name: &str) -> Option<Variable> {
+        let scope = self.0.borrow();
+        match scope.variables.get(name) {
+            Some(variable) => Some(variable.clone()),
+            None => match &scope.parent {
+                Some(parent) => parent.get(name),
+                None => None,
+            },
+        }
+    }
+
+    fn declare(&self, name: &str, variable: Variable) {
+        self.0
+            .borrow_mut()
+            .variables
+            .insert(name.to_string(), variable);
+    }
+
+    fn assign(&self, name: &str, variable: Variable) {
+        let mut scope = self.0.borrow_mut();
+        if scope.variables.contains_key(name) {
+            scope.variables.insert(name.to_string(), variable);
+            return;
+        }
+        match &scope.parent {
+            Some(parent) => parent.assign(name, variable),
+            None => {
+                fail("runtime", format!("{name} is not declared"));
+            }
+        }
+    }
+}
+
+// scopes can contain functions that captured them, so compare and print by identity
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Environment")
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Tree {
+    root: Node,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Node {
+    id: usize,
+    data: Data,
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn new() -> Self {
+        Tree {
+            root: Node {
+                id: 0,
+                data: Data::Main,
+                nodes: Vec::new(),
+            },
+        }
+    }
+
+    fn get_scope(&mut self, scope: Vec<usize>) -> &mut Node {
+        self.root.get_scope(scope)
+    }
+}
+
+impl Node {
+    fn insert(&mut self, item: &Data) -> &mut Node {
+        let mut node = self;
+
         let next_node_idx = Node::get_child_idx(&node.nodes, node.nodes.len());
 
         node = match next_node_idx {
@@ -512,9 +1868,36 @@ struct Parser {
     tokens: Vec<Token>,
     token: Token,
     end: bool,
+    variants: HashMap<String, Vec<(String, usize)>>,
+}
+
+// parameters, how many of them are required and whether the last one is variadic
+type Signature = (Vec<String>, usize, bool);
+
+fn signature(node: &Node) -> Option<Signature> {
+    match &node.data {
+        Data::FunctionContainer {
+            params,
+            defaults,
+            variadic,
+            ..
+        } => {
+            let mut required = defaults.iter().filter(|x| x.is_none()).count();
+            if *variadic {
+                required -= 1;
+            }
+            Some((params.to_vec(), required, *variadic))
+        }
+        _ => None,
+    }
 }
 
 impl Parser {
+    fn fail(&self, message: String) -> ! {
+        LINE.set(self.token.line);
+        fail("syntax", message)
+    }
+
     fn step(&mut self) {
         self.index += 1;
         if self.index < self.tokens.len() {
@@ -531,17 +1914,117 @@ impl Parser {
             self.build_tree(&mut ast, &mut scope);
             self.step();
         }
+        self.check_arity(&ast.root, &HashMap::new());
         ast
     }
 
+    // `visible` maps each name in scope to its signature if it is a named function,
+    // so calls through a parameter or variable that shadows one are not checked
+    fn check_arity(&self, node: &Node, visible: &HashMap<String, Option<Signature>>) {
+        let mut names: Vec<(String, Option<Signature>)> = match &node.data {
+            Data::FunctionContainer { params, .. } => {
+                params.iter().map(|x| (x.clone(), None)).collect()
+            }
+            Data::Case { bindings, .. } => bindings.iter().map(|x| (x.clone(), None)).collect(),
+            Data::Catch { name } => [(name.clone(), None)].to_vec(),
+            _ => Vec::new(),
+        };
+        for sub_node in &node.nodes {
+            match (&sub_node.data, sub_node.nodes.first().map(|x| &x.data)) {
+                (Data::Declare | Data::Define, Some(Data::Identifier { name })) => {
+                    names.push((name.clone(), sub_node.nodes.get(1).and_then(signature)));
+                }
+                (Data::Import { name, .. }, _) => names.push((name.clone(), None)),
+                _ => {}
+            }
+        }
+        let mut scoped;
+        let visible = match names.is_empty() {
+            true => visible,
+            false => {
+                scoped = visible.clone();
+                scoped.extend(names);
+                &scoped
+            }
+        };
+        if let Data::Call { name } = &node.data {
+            if let Some(Some((params, required, variadic))) = visible.get(name) {
+                let positional = node
+                    .nodes
+                    .iter()
+                    .filter(|x| !matches!(x.data, Data::Argument { .. }))
+                    .count();
+                if positional > params.len() && !variadic {
+                    fail(
+                        "syntax",
+                        format!(
+                            "{name} expects at most {} arguments but received {positional}",
+                            params.len()
+                        ),
+                    );
+                }
+                for argument in &node.nodes {
+                    if let Data::Argument { name: param } = &argument.data {
+                        match params.iter().position(|x| x == param) {
+                            Some(i) if i < positional => {
+                                fail("syntax", format!("{name} received {param} twice"));
+                            }
+                            Some(_) => {}
+                            None => {
+                                fail("syntax", format!("{name} has no parameter {param}"));
+                            }
+                        }
+                    }
+                }
+                for param in &params[positional.min(*required)..*required] {
+                    if !node.nodes.iter().any(|x| {
+                        x.data
+                            == (Data::Argument {
+                                name: param.clone(),
+                            })
+                    }) {
+                        fail(
+                            "syntax",
+                            format!("{name} is missing an argument for {param}"),
+                        );
+                    }
+                }
+            }
+        }
+        for sub_node in &node.nodes {
+            if let Data::Line { number } = sub_node.data {
+                LINE.set(number);
+            }
+            self.check_arity(sub_node, visible);
+        }
+    }
+
     fn build_tree(&mut self, ast: &mut Tree, scope: &mut Vec<usize>) {
+        if matches!(
+            self.token.category,
+            Meta::KEY
+                | Meta::REF
+                | Meta::TXT
+                | Meta::TYP
+                | Meta::WHL
+                | Meta::INT
+                | Meta::FLT
+                | Meta::BLN
+                | Meta::FUN
+        ) || self.token.value == "("
+        {
+            // lets runtime errors report where they happened
+            ast.get_scope(scope.clone()).insert(&Data::Line {
+                number: self.token.line,
+            });
+        }
         match self.token.category {
             Meta::KEY => {
                 match self.token.value.as_str() {
                     "declare" => {
                         self.step();
                         if self.token.category != Meta::REF {
-                            panic!("expected REF");
+                            self.fail("expected REF".to_string());
                         }
                         let node = ast.get_scope(scope.clone()).insert(&Data::Declare);
                         node.insert(&Data::Identifier {
@@ -550,8 +2033,9 @@ impl Parser {
                         self.step();
                         if self.token.value == "as" {
                             self.step();
-                            if self.token.category != Meta::TYP {
-                                panic!("expected TYP");
+                            if self.token.category != Meta::TYP && self.token.category != Meta::REF
+                            {
+                                self.fail("expected TYP".to_string());
                             }
                             let sub_node = node.insert(&Data::Type {
                                 name: self.token.value.clone(),
@@ -561,92 +2045,168 @@ impl Parser {
                     }
                     "set" => {
                         self.step();
-                        if self.token.category != Meta::REF {
-                            panic!("expected REF");
+                        if self.token.category != Meta::REF && self.token.value != "(" {
+                            self.fail("expected REF".to_string());
+                        }
+                        let node = ast.get_scope(scope.clone()).insert(&Data::Assign);
+                        if self.token.value == "(" {
+                            // destructuring assignment
+                            let sub_node = node.insert(&Data::Tuple { value: [].to_vec() });
+                            self.step();
+                            while !self.end && self.token.value != ")" {
+                                if self.token.category != Meta::REF {
+                                    self.fail("expected REF".to_string());
+                                }
+                                sub_node.insert(&Data::Identifier {
+                                    name: self.token.value.clone(),
+                                });
+                                self.step();
+                            }
+                        } else {
+                            node.insert(&Data::Identifier {
+                                name: self.token.value.clone(),
+                            });
                         }
-                        let scoped_node = ast.get_scope(scope.clone());
-                        scope.push(scoped_node.nodes.len());
-                        let node = scoped_node.insert(&Data::Assign);
-                        node.insert(&Data::Identifier {
-                            name: self.token.value.clone(),
-                        });
                         self.step();
                         if self.token.value == "to" {
                             self.step();
-                            // different rules for functions
-                            match self.token.category {
-                                Meta::FUN => {
-                                    let mut params: Vec<String> = Vec::new();
-                                    let mut param_types: Vec<Data> = Vec::new();
+                            self.build_expression(node);
+                        }
+                    }
+                    "define" => {
+                        self.step();
+                        if self.token.category != Meta::REF {
+                            self.fail("expected REF".to_string());
+                        }
+                        let name = self.token.value.clone();
+                        let node = ast.get_scope(scope.clone()).insert(&Data::Define);
+                        node.insert(&Data::Identifier { name: name.clone() });
+                        if self.index < self.tokens.len() - 1
+                            && self.tokens[self.index + 1].value == "("
+                        {
+                            self.build_function(node);
+                            return;
+                        }
+                        self.step();
+                        if self.token.value != "as" {
+                            self.fail("expected `as` after defined name".to_string());
+                        }
+                        self.step();
+                        let sub_node = node.insert(&Data::Type {
+                            name: self.token.value.clone(),
+                        });
+                        match self.token.value.as_str() {
+                            "record" => {
+                                self.step();
+                                if self.token.value != "{" {
+                                    self.fail("expected opening of record body".to_string());
+                                }
+                                self.build_fields(sub_node, "}");
+                            }
+                            "enum" => {
+                                self.step();
+                                if self.token.value != "{" {
+                                    self.fail("expected opening of enum body".to_string());
+                                }
+                                let mut variants: Vec<(String, usize)> = Vec::new();
+                                self.step();
+                                while !self.end && self.token.value != "}" {
+                                    if self.token.category != Meta::REF {
+                                        self.fail("expected variant name".to_string());
+                                    }
+                                    let tag = self.token.value.clone();
+                                    // tags are declared as global values, so they must be unique
+                                    // for a `match` to know which enum it is on
+                                    if variants.iter().any(|x| x.0 == tag) {
+                                        self.fail(format!(
+                                            "variant {tag} is defined twice in {name}"
+                                        ));
+                                    }
+                                    if let Some((other, _)) = self
+                                        .variants
+                                        .iter()
+                                        .find(|(_, variants)| variants.iter().any(|x| x.0 == tag))
+                                    {
+                                        self.fail(format!("variant {tag} of {name} is already a variant of {other}"));
+                                    }
+                                    let variant =
+                                        sub_node.insert(&Data::Identifier { name: tag.clone() });
                                     if self.index < self.tokens.len() - 1
                                         && self.tokens[self.index + 1].value == "("
                                     {
                                         self.step();
-                                        let mut counter: usize = 1;
-                                        self.step();
-                                        while !self.end && counter != 0 {
-                                            if self.token.value == "(" {
-                                                counter += 1;
-                                            } else if self.token.value == ")" {
-                                                counter -= 1;
-                                            } else if self.token.category == Meta::REF {
-                                                params.push(self.token.value.clone());
-                                                self.step();
-                                                if self.token.value == "as" {
-                                                    self.step();
-                                                    if self.token.category != Meta::TYP {
-                                                        panic!("expected TYP");
-                                                    }
-                                                    param_types.push(Data::Type {
-                                                        name: self.token.value.clone(),
-                                                    });
-                                                }
-                                            } else {
-                                                // other feature
-                                            }
-                                            self.step();
-                                        }
-                                        let return_type = if self.token.value == "as" {
-                                            self.step();
-                                            if self.token.category != Meta::TYP {
-                                                panic!("expected TYP");
-                                            }
-                                            Data::Type {
-                                                name: self.token.value.clone(),
-                                            }
-                                        } else {
-                                            panic!("expected function return type");
-                                        };
-                                        self.step();
-                                        scope.push(node.nodes.len());
-                                        node.insert(&Data::FunctionContainer {
-                                            params,
-                                            param_types,
-                                            return_types: [return_type].to_vec(),
-                                        });
-                                        let mut counter: usize = 1;
-                                        self.step();
-                                        while !self.end && counter != 0 {
-                                            if self.token.value == "{" {
-                                                counter += 1;
-                                            } else if self.token.value == "}" {
-                                                counter -= 1;
-                                            } else {
-                                                self.build_tree(ast, scope);
-                                            }
-                                            self.step();
-                                        }
-                                        self.back();
-                                        scope.pop(); // descope
-                                        scope.pop(); // descope
+                                        self.build_fields(variant, ")");
                                     }
+                                    variants.push((tag, variant.nodes.len()));
+                                    self.step();
                                 }
-                                _ => {
-                                    scope.pop(); // descope
-                                    self.build_expression(node);
+                                self.variants.insert(name, variants);
+                            }
+                            _ => {
+                                self.fail("expected record or enum definition".to_string());
+                            }
+                        }
+                    }
+                    "match" => {
+                        self.step();
+                        let scoped_node = ast.get_scope(scope.clone());
+                        scope.push(scoped_node.nodes.len());
+                        let node = scoped_node.insert(&Data::Match);
+                        let sub_node = node.insert(&Data::Expression);
+                        while !self.end && !matches!(self.token.category, Meta::BRC) {
+                            self.build_expression(sub_node);
+                            self.step();
+                        }
+                        if self.token.value != "{" {
+                            self.fail("expected opening of match body".to_string());
+                        }
+                        let mut cases: Vec<(String, usize)> = Vec::new();
+                        self.step();
+                        while !self.end && self.token.value != "}" {
+                            if self.token.value != "case" {
+                                self.fail(format!("expected case but got {:?}", self.token));
+                            }
+                            self.step();
+                            if self.token.category != Meta::REF {
+                                self.fail("expected variant name".to_string());
+                            }
+                            let tag = self.token.value.clone();
+                            let mut bindings: Vec<String> = Vec::new();
+                            self.step();
+                            if self.token.value == "(" {
+                                self.step();
+                                while !self.end && self.token.value != ")" {
+                                    if self.token.category != Meta::REF {
+                                        self.fail("expected binding name".to_string());
+                                    }
+                                    bindings.push(self.token.value.clone());
+                                    self.step();
+                                }
+                                self.step();
+                            }
+                            if self.token.value != "{" {
+                                self.fail("expected opening of case body".to_string());
+                            }
+                            cases.push((tag.clone(), bindings.len()));
+                            let case_node = ast.get_scope(scope.clone());
+                            scope.push(case_node.nodes.len());
+                            case_node.insert(&Data::Case { tag, bindings });
+                            let mut counter: usize = 1;
+                            self.step();
+                            while !self.end && counter != 0 {
+                                if self.token.value == "{" {
+                                    counter += 1;
+                                } else if self.token.value == "}" {
+                                    counter -= 1;
+                                } else {
+                                    self.build_tree(ast, scope);
                                 }
+                                self.step();
                             }
+                            scope.pop(); // descope
                         }
+                        self.check_cases(&cases);
+                        scope.pop(); // descope
                     }
                     "while" => {
                         self.step();
@@ -656,14 +2216,14 @@ impl Parser {
                         let node = scoped_node.insert(&Data::While);
                         let sub_node = node.insert(&Data::Expression);
                         if self.index >= self.tokens.len() - 1 {
-                            panic!("loop is missing a body");
+                            self.fail("loop is missing a body".to_string());
                         }
                         while !self.end && !matches!(self.token.category, Meta::BRC) {
                             self.build_expression(sub_node);
                             self.step();
                         }
                         if self.token.value != "{" {
-                            panic!("expected opening of loop body");
+                            self.fail("expected opening of loop body".to_string());
                         }
                         let mut counter: usize = 1;
                         self.step();
@@ -680,11 +2240,101 @@ impl Parser {
                         self.back();
                         scope.pop(); // descope
                     }
+                    "import" => {
+                        self.step();
+                        if self.token.category != Meta::TXT {
+                            self.fail("expected path of imported file".to_string());
+                        }
+                        let path = self.token.value.clone();
+                        self.step();
+                        if self.token.value != "as" {
+                            self.fail("expected `as` after imported path".to_string());
+                        }
+                        self.step();
+                        if self.token.category != Meta::REF {
+                            self.fail("expected REF".to_string());
+                        }
+                        ast.get_scope(scope.clone()).insert(&Data::Import {
+                            path,
+                            name: self.token.value.clone(),
+                        });
+                    }
+                    "export" => {
+                        self.step();
+                        if !["define", "declare"].contains(&self.token.value.as_str())
+                            || self.index >= self.tokens.len() - 1
+                        {
+                            self.fail("expected define or declare after export".to_string());
+                        }
+                        if self.token.value == "define"
+                            && self
+                                .tokens
+                                .get(self.index + 3)
+                                .is_some_and(|x| ["record", "enum"].contains(&x.value.as_str()))
+                        {
+                            // the interpreter keeps types and variants out of module records
+                            self.fail("only functions and variables can be exported".to_string());
+                        }
+                        ast.get_scope(scope.clone()).insert(&Data::Export {
+                            name: self.tokens[self.index + 1].value.clone(),
+                        });
+                        self.build_tree(ast, scope);
+                    }
                     "return" => {
                         let node = ast.get_scope(scope.clone()).insert(&Data::Return);
                         self.step();
                         self.build_expression(node);
                     }
+                    "try" => {
+                        self.step();
+                        let scoped_node = ast.get_scope(scope.clone());
+                        scope.push(scoped_node.nodes.len());
+                        scoped_node.insert(&Data::Try);
+                        if self.token.value != "{" {
+                            self.fail("expected opening of try body".to_string());
+                        }
+                        self.build_block(ast, scope);
+                        let mut handled = false;
+                        if self.index < self.tokens.len() - 1
+                            && self.tokens[self.index + 1].value == "catch"
+                        {
+                            self.step();
+                            self.step();
+                            if self.token.category != Meta::REF {
+                                self.fail("expected name of caught error".to_string());
+                            }
+                            let name = self.token.value.clone();
+                            self.step();
+                            if self.token.value != "{" {
+                                self.fail("expected opening of catch body".to_string());
+                            }
+                            let node = ast.get_scope(scope.clone());
+                            scope.push(node.nodes.len());
+                            node.insert(&Data::Catch { name });
+                            self.build_block(ast, scope);
+                            scope.pop(); // descope
+                            handled = true;
+                        }
+                        if self.index < self.tokens.len() - 1
+                            && self.tokens[self.index + 1].value == "finally"
+                        {
+                            self.step();
+                            self.step();
+                            if self.token.value != "{" {
+                                self.fail("expected opening of finally body".to_string());
+                            }
+                            let node = ast.get_scope(scope.clone());
+                            scope.push(node.nodes.len());
+                            node.insert(&Data::Finally);
+                            self.build_block(ast, scope);
+                            scope.pop(); // descope
+                            handled = true;
+                        }
+                        if !handled {
+                            self.fail("expected catch or finally after try".to_string());
+                        }
+                        scope.pop(); // descope
+                    }
                     _ => {}
                 }
             }
@@ -694,19 +2344,283 @@ impl Parser {
                 });
             }
             Meta::REF => {
+                if self.token.value == "raise" {
+                    let node = ast.get_scope(scope.clone()).insert(&Data::Raise);
+                    self.step();
+                    self.build_expression(node);
+                } else {
+                    self.build_expression(ast.get_scope(scope.clone()));
+                }
+            }
+            Meta::WHL | Meta::INT | Meta::FLT | Meta::BLN | Meta::FUN => {
+                self.build_expression(ast.get_scope(scope.clone()));
+            }
+            Meta::PAR if self.token.value == "(" => {
                 self.build_expression(ast.get_scope(scope.clone()));
             }
             Meta::TYP => {
-                ast.get_scope(scope.clone()).insert(&Data::Type {
+                let next = self.tokens.get(self.index + 1).map(|x| x.value.as_str());
+                // `map[key value](...)` is a map literal, so print it like any other expression
+                if next == Some("(") || (self.token.value == "map" && next == Some("[")) {
+                    self.build_expression(ast.get_scope(scope.clone()));
+                } else {
+                    ast.get_scope(scope.clone()).insert(&Data::Type {
+                        name: self.token.value.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn build_block(&mut self, ast: &mut Tree, scope: &mut Vec<usize>) {
+        let mut counter: usize = 1;
+        self.step();
+        while !self.end && counter != 0 {
+            if self.token.value == "{" {
+                counter += 1;
+            } else if self.token.value == "}" {
+                counter -= 1;
+            } else {
+                self.build_tree(ast, scope);
+            }
+            self.step();
+        }
+        self.back();
+    }
+
+    fn build_function(&mut self, node: &mut Node) {
+        let mut params: Vec<String> = Vec::new();
+        let mut param_types: Vec<Node> = Vec::new();
+        let mut defaults: Vec<Option<Node>> = Vec::new();
+        let mut variadic = false;
+        if self.index >= self.tokens.len() - 1 || self.tokens[self.index + 1].value != "(" {
+            self.fail("expected function parameters".to_string());
+        }
+        self.step();
+        let mut counter: usize = 1;
+        self.step();
+        while !self.end && counter != 0 {
+            if self.token.value == "(" {
+                counter += 1;
+            } else if self.token.value == ")" {
+                counter -= 1;
+            } else if self.token.category == Meta::REF {
+                if variadic {
+                    self.fail("rest parameter must be last".to_string());
+                }
+                params.push(self.token.value.clone());
+                self.step();
+                if self.token.value == "as" {
+                    self.step();
+                    param_types.push(self.build_type());
+                }
+                if self.index < self.tokens.len() - 1
+                    && self.tokens[self.index + 1].category == Meta::Ellipsis
+                {
+                    // rest parameter collecting the remaining arguments
+                    if param_types.len() != params.len()
+                        || expect_type(&param_types.last().unwrap().data) != "list"
+                    {
+                        self.fail("rest parameter must be a list".to_string());
+                    }
+                    variadic = true;
+                    defaults.push(None);
+                    self.step();
+                } else if self.index < self.tokens.len() - 1
+                    && self.tokens[self.index + 1].category == Meta::Equals
+                {
+                    self.step();
+                    self.step();
+                    let mut default = Node {
+                        id: 0,
+                        data: Data::Expression,
+                        nodes: Vec::new(),
+                    };
+                    self.build_expression(&mut default);
+                    defaults.push(Some(default));
+                } else if defaults.iter().any(|x| x.is_some()) {
+                    self.fail(format!(
+                        "parameter {} needs a default value",
+                        params.last().unwrap()
+                    ));
+                } else {
+                    defaults.push(None);
+                }
+            } else {
+                // other feature
+            }
+            self.step();
+        }
+        let mut return_types: Vec<Node> = Vec::new();
+        if self.token.value != "as" {
+            self.fail("expected function return type".to_string());
+        }
+        self.step();
+        if self.token.value == "(" {
+            // multiple return values
+            self.step();
+            while !self.end && self.token.value != ")" {
+                return_types.push(self.build_type());
+                self.step();
+            }
+        } else {
+            return_types.push(self.build_type());
+        }
+        self.step();
+        if self.token.value != "{" {
+            self.fail("expected opening of function body".to_string());
+        }
+        // the body is parsed on its own so functions can appear in any expression
+        let mut body = Tree::new();
+        let mut scope: Vec<usize> = [].to_vec();
+        let mut counter: usize = 1;
+        self.step();
+        while !self.end && counter != 0 {
+            if self.token.value == "{" {
+                counter += 1;
+            } else if self.token.value == "}" {
+                counter -= 1;
+            } else {
+                self.build_tree(&mut body, &mut scope);
+            }
+            self.step();
+        }
+        self.back();
+        let sub_node = node.insert(&Data::FunctionContainer {
+            params,
+            param_types,
+            return_types,
+            defaults,
+            variadic,
+        });
+        sub_node.nodes = body.root.nodes;
+    }
+
+    fn build_call(&mut self, node: &mut Node) {
+        let sub_node = node.insert(&Data::Call {
+            name: self.token.value.clone(),
+        });
+        self.step();
+        let mut counter: usize = 1;
+        self.step();
+        while !self.end && counter != 0 {
+            if self.token.value == "(" {
+                counter += 1;
+            } else if self.token.value == ")" {
+                counter -= 1;
+            } else if self.token.category == Meta::REF
+                && self.index < self.tokens.len() - 1
+                && self.tokens[self.index + 1].category == Meta::Colon
+            {
+                // named argument
+                let argument = sub_node.insert(&Data::Argument {
                     name: self.token.value.clone(),
                 });
+                self.step();
+                self.step();
+                self.build_expression(argument);
+            } else {
+                self.build_expression(sub_node);
+            }
+            self.step();
+        }
+        self.back();
+    }
+
+    fn build_fields(&mut self, node: &mut Node, closing: &str) {
+        self.step();
+        while !self.end && self.token.value != closing {
+            if self.token.category != Meta::REF {
+                self.fail("expected field name".to_string());
+            }
+            let field = node.insert(&Data::Declare);
+            field.insert(&Data::Identifier {
+                name: self.token.value.clone(),
+            });
+            self.step();
+            if self.token.value != "as" {
+                self.fail("expected field type".to_string());
+            }
+            self.step();
+            if self.token.category != Meta::TYP && self.token.category != Meta::REF {
+                self.fail("expected TYP".to_string());
+            }
+            let type_node = field.insert(&Data::Type {
+                name: self.token.value.clone(),
+            });
+            self.nest_types(type_node);
+            self.step();
+        }
+    }
+
+    fn check_cases(&self, cases: &Vec<(String, usize)>) {
+        if cases.is_empty() {
+            self.fail("match has no cases".to_string());
+        }
+        let tag = match cases.iter().find(|x| x.0 != "_") {
+            Some(x) => &x.0,
+            None => return,
+        };
+        let (name, variants) = match self
+            .variants
+            .iter()
+            .find(|(_, variants)| variants.iter().any(|x| x.0 == *tag))
+        {
+            Some(x) => x,
+            None => {
+                self.fail(format!("{tag} is not a variant of any enum"));
+            }
+        };
+        for case in cases {
+            if case.0 == "_" {
+                continue;
+            }
+            match variants.iter().find(|x| x.0 == case.0) {
+                Some(variant) => {
+                    if variant.1 != case.1 {
+                        self.fail(format!(
+                            "case {} binds {} values but the variant carries {}",
+                            case.0, case.1, variant.1
+                        ));
+                    }
+                }
+                None => {
+                    self.fail(format!("{} is not a variant of {name}", case.0));
+                }
+            }
+        }
+        if cases.iter().any(|x| x.0 == "_") {
+            return;
+        }
+        for variant in variants {
+            if !cases.iter().any(|x| x.0 == variant.0) {
+                self.fail(format!(
+                    "match on {name} is not exhaustive: missing case {}",
+                    variant.0
+                ));
             }
-            _ => {}
         }
     }
 
+    fn build_type(&mut self) -> Node {
+        if self.token.category != Meta::TYP && self.token.category != Meta::REF {
+            self.fail("expected TYP".to_string());
+        }
+        let mut node = Node {
+            id: 0,
+            data: Data::Type {
+                name: self.token.value.clone(),
+            },
+            nodes: Vec::new(),
+        };
+        self.nest_types(&mut node);
+        node
+    }
+
     fn nest_types(&mut self, node: &mut Node) {
         if self.index < self.tokens.len() - 1 && self.tokens[self.index + 1].value == "[" {
+            let mut arrow: Option<usize> = None;
             self.step();
             let mut counter: usize = 1;
             self.step();
@@ -715,7 +2629,9 @@ impl Parser {
                     counter += 1;
                 } else if self.token.value == "]" {
                     counter -= 1;
-                } else if self.token.category == Meta::TYP {
+                } else if self.token.category == Meta::Arrow {
+                    arrow = Some(node.nodes.len());
+                } else if self.token.category == Meta::TYP || self.token.category == Meta::REF {
                     // TODO: support recursive typing
                     let sub_node = node.insert(&Data::Type {
                         name: self.token.value.clone(),
@@ -726,6 +2642,21 @@ impl Parser {
                 }
                 self.step();
             }
+            if node.data
+                == (Data::Type {
+                    name: "function".to_string(),
+                })
+            {
+                // signatures are stored as `function[tuple[params] tuple[returns]]`
+                let returns = match arrow {
+                    Some(x) => node.nodes.split_off(x),
+                    None => {
+                        self.fail("expected -> in function type".to_string());
+                    }
+                };
+                let params = mem::take(&mut node.nodes);
+                node.nodes = [tuple_type(0, params), tuple_type(1, returns)].to_vec();
+            }
             self.back();
         }
     }
@@ -763,34 +2694,111 @@ impl Parser {
                 });
             }
             Meta::REF => {
-                let sub_node = node.insert(&Data::Identifier {
-                    name: self.token.value.clone(),
-                });
                 if self.index < self.tokens.len() - 1 && self.tokens[self.index + 1].value == "(" {
+                    self.build_call(node);
+                } else {
+                    node.insert(&Data::Identifier {
+                        name: self.token.value.clone(),
+                    });
+                }
+            }
+            Meta::PAR if self.token.value == "(" => {
+                let sub_node = node.insert(&Data::Tuple { value: [].to_vec() });
+                let mut counter: usize = 1;
+                self.step();
+                while !self.end && counter != 0 {
+                    if self.token.value == "(" {
+                        counter += 1;
+                    } else if self.token.value == ")" {
+                        counter -= 1;
+                    } else {
+                        self.build_expression(sub_node);
+                    }
                     self.step();
-                    let mut counter: usize = 1;
-                    self.step();
-                    while !self.end && counter != 0 {
-                        if self.token.value == "(" {
-                            counter += 1;
-                        } else if self.token.value == ")" {
-                            counter -= 1;
-                        } else {
-                            self.build_expression(sub_node);
-                        }
-                        self.step();
+                }
+                self.back();
+            }
+            Meta::FUN => {
+                self.build_function(node);
+            }
+            // `map(...)` is the builtin while `map[...](...)` is a literal
+            Meta::TYP
+                if self.token.value == "map"
+                    && self.index < self.tokens.len() - 1
+                    && self.tokens[self.index + 1].value == "(" =>
+            {
+                self.build_call(node);
+            }
+            Meta::TYP => {
+                if self.token.value != "map" {
+                    self.fail(format!("expected expression but got {:?}", self.token));
+                }
+                let mut type_node = Node {
+                    id: 0,
+                    data: Data::Type {
+                        name: self.token.value.clone(),
+                    },
+                    nodes: Vec::new(),
+                };
+                self.nest_types(&mut type_node);
+                if type_node.nodes.len() != 2 {
+                    self.fail("expected key and value types".to_string());
+                }
+                let sub_node = node.insert(&Data::Map {
+                    value: [].to_vec(),
+                    sub_type: type_node.nodes,
+                });
+                self.step();
+                if self.token.value != "(" {
+                    self.fail("expected opening of map entries".to_string());
+                }
+                let mut counter: usize = 1;
+                self.step();
+                while !self.end && counter != 0 {
+                    if self.token.value == "(" {
+                        counter += 1;
+                    } else if self.token.value == ")" {
+                        counter -= 1;
+                    } else {
+                        self.build_expression(sub_node);
                     }
-                    self.back();
+                    self.step();
                 }
+                self.back();
             }
             _ => {
                 println!("{node:?}");
-                panic!("expected expression but got {:?}", self.token);
+                self.fail(format!("expected expression but got {:?}", self.token));
             }
         }
     }
 }
 
+fn tuple_type(id: usize, nodes: Vec<Node>) -> Node {
+    Node {
+        id,
+        data: Data::Type {
+            name: "tuple".to_string(),
+        },
+        nodes: nodes
+            .into_iter()
+            .enumerate()
+            .map(|(id, x)| Node {
+                id,
+                data: x.data,
+                nodes: x.nodes,
+            })
+            .collect(),
+    }
+}
+
+fn return_type_nodes(return_types: &[Node]) -> Vec<Node> {
+    if return_types.len() == 1 {
+        return return_types.to_vec();
+    }
+    [tuple_type(0, return_types.to_vec())].to_vec()
+}
+
 fn new_node_vec(data: Data) -> Vec<Node> {
     [Node {
         id: 0,
@@ -800,32 +2808,454 @@ fn new_node_vec(data: Data) -> Vec<Node> {
     .to_vec()
 }
 
+thread_local! {
+    // line of the statement being parsed or run and how many try blocks are active
+    static LINE: Cell<usize> = const { Cell::new(0) };
+    static TRYING: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug, Clone)]
+struct KraberError {
+    kind: String,
+    message: String,
+    line: usize,
+}
+
+impl KraberError {
+    fn from_payload(payload: &(dyn Any + Send)) -> KraberError {
+        if let Some(error) = payload.downcast_ref::<KraberError>() {
+            return error.clone();
+        }
+        let message = match payload.downcast_ref::<String>() {
+            Some(message) => message.to_string(),
+            None => payload.downcast_ref::<&str>().unwrap_or(&"").to_string(),
+        };
+        KraberError {
+            kind: "runtime".to_string(),
+            message,
+            line: LINE.get(),
+        }
+    }
+
+    fn from_record(fields: &[(String, Variable)]) -> KraberError {
+        let field = |name: &str| match fields.iter().find(|x| x.0 == name) {
+            Some(x) => x.1.value.clone(),
+            None => {
+                fail("type", format!("error has no field {name}"));
+            }
+        };
+        match (field("kind"), field("message"), field("line")) {
+            (
+                Data::Text { value: kind },
+                Data::Text { value: message },
+                Data::Whole { value: line },
+            ) => KraberError {
+                kind,
+                message,
+                line,
+            },
+            _ => {
+                fail("type", "malformed error record".to_string());
+            }
+        }
+    }
+
+    fn to_record(&self) -> Data {
+        let field = |name: &str, value: Data| {
+            (
+                name.to_string(),
+                Variable {
+                    data_type: [type_node(&value)].to_vec(),
+                    value,
+                },
+            )
+        };
+        Data::Record {
+            name: "error".to_string(),
+            fields: [
+                field(
+                    "message",
+                    Data::Text {
+                        value: self.message.clone(),
+                    },
+                ),
+                field(
+                    "kind",
+                    Data::Text {
+                        value: self.kind.clone(),
+                    },
+                ),
+                field("line", Data::Whole { value: self.line }),
+            ]
+            .to_vec(),
+        }
+    }
+}
+
+fn fail(kind: &str, message: String) -> ! {
+    panic::panic_any(KraberError {
+        kind: kind.to_string(),
+        message,
+        line: LINE.get(),
+    })
+}
+
+// raised by `exit`, which ends the program instead of being caught
+struct Exit(i32);
+
+fn attempt(body: impl FnOnce()) -> Result<(), KraberError> {
+    TRYING.set(TRYING.get() + 1);
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    TRYING.set(TRYING.get() - 1);
+    // only kraber errors can be caught, anything else is an exit or a bug in the interpreter
+    result.map_err(|payload| match payload.downcast::<KraberError>() {
+        Ok(error) => *error,
+        Err(payload) => panic::resume_unwind(payload),
+    })
+}
+
+// collects printed text in memory instead of writing it out
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).to_string()
+    }
+}
+
+// hands printed text to a function of the host as it is written
+#[allow(dead_code)] // only used by hosts embedding the interpreter
+struct Callback<F: FnMut(&[u8])>(F);
+
+impl<F: FnMut(&[u8])> Write for Callback<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// directories a program may read or write below, nothing is allowed by default
+#[derive(Debug, Clone, Default)]
+struct Capabilities {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+impl Capabilities {
+    fn allow(directories: &mut Vec<PathBuf>, path: &str) {
+        match fs::canonicalize(path) {
+            Ok(path) => directories.push(path),
+            Err(_) => {
+                panic!("could not find {path} to allow access to");
+            }
+        }
+    }
+
+    fn check(&self, path: &str, write: bool) -> PathBuf {
+        // resolved so that `..` and links cannot leave the allowed directories,
+        // a file that does not exist yet is resolved through its parent
+        let resolved = fs::canonicalize(path).ok().or_else(|| {
+            // a link whose target does not exist cannot be resolved, and writing
+            // through it would create its target wherever that is
+            if fs::symlink_metadata(path).is_ok() {
+                return None;
+            }
+            Capabilities::resolve_parent(path)
+        });
+        self.allowed(path, resolved, write)
+    }
+
+    // checks a link itself instead of what it points to, for removing it
+    fn check_entry(&self, path: &str, write: bool) -> PathBuf {
+        self.allowed(path, Capabilities::resolve_parent(path), write)
+    }
+
+    fn resolve_parent(path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        let parent = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => return None,
+        };
+        Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+    }
+
+    fn allowed(&self, path: &str, resolved: Option<PathBuf>, write: bool) -> PathBuf {
+        let (directories, access) = match write {
+            true => (&self.write, "write"),
+            false => (&self.read, "read"),
+        };
+        match resolved {
+            Some(resolved) if directories.iter().any(|x| resolved.starts_with(x)) => resolved,
+            _ => fail("permission", format!("no {access} access to {path}")),
+        }
+    }
+}
+
+// where a program gets the time from, the host can pass its own clock to make runs repeatable
+trait Clock {
+    // milliseconds since the unix epoch
+    fn now(&self) -> u64;
+    // milliseconds since some fixed point, never goes backwards
+    fn monotonic(&self) -> u64;
+    fn sleep(&self, millis: u64);
+}
+
+struct SystemClock(Instant);
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        time.as_millis() as u64
+    }
+
+    fn monotonic(&self) -> u64 {
+        self.0.elapsed().as_millis() as u64
+    }
+
+    fn sleep(&self, millis: u64) {
+        thread::sleep(Duration::from_millis(millis));
+    }
+}
+
+// a clock that only moves when the program sleeps
+#[derive(Default)]
+struct ManualClock {
+    now: Cell<u64>,
+    monotonic: Cell<u64>,
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.get()
+    }
+
+    fn monotonic(&self) -> u64 {
+        self.monotonic.get()
+    }
+
+    fn sleep(&self, millis: u64) {
+        self.now.set(self.now.get() + millis);
+        self.monotonic.set(self.monotonic.get() + millis);
+    }
+}
+
+type Modules = Rc<RefCell<HashMap<PathBuf, Option<Vec<(String, Variable)>>>>>;
+
 struct Interpreter {
     tree: Tree,
-    memory: HashMap<String, Variable>,
-    locals: Vec<String>,
+    memory: Environment,
+    // where a program reads from and prints to, the host can pass any reader and writer
+    input: Rc<RefCell<dyn BufRead>>,
+    output: Rc<RefCell<dyn Write>>,
+    capabilities: Rc<Capabilities>,
+    // file being run, imports are found relative to it
+    file: PathBuf,
+    // exports of every imported file, `None` while it is still being loaded
+    modules: Modules,
+    depth: usize,
+    max_depth: usize,
+    tail_calls: bool,
+    // set once a `return` runs, shared by the blocks of one function call
+    returned: Rc<Cell<bool>>,
+    // state of the random number generator, shared so `seed` affects the whole program
+    random: Rc<Cell<u64>>,
+    clock: Rc<dyn Clock>,
 }
 
 impl Interpreter {
+    fn sub_interpreter(&self, body: Vec<Node>, memory: Environment) -> Interpreter {
+        Interpreter {
+            tree: Tree {
+                root: Node {
+                    id: 0,
+                    data: Data::Main,
+                    nodes: body,
+                },
+            },
+            memory,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            capabilities: self.capabilities.clone(),
+            file: self.file.clone(),
+            modules: self.modules.clone(),
+            depth: self.depth,
+            max_depth: self.max_depth,
+            tail_calls: self.tail_calls,
+            returned: self.returned.clone(),
+            random: self.random.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    fn write(&self, text: &str) {
+        let mut output = self.output.borrow_mut();
+        output
+            .write_all(text.as_bytes())
+            .and_then(|_| output.flush())
+            .expect("could not write output");
+    }
+
+    fn write_line(&self, text: &str) {
+        self.write(&format!("{text}\n"));
+    }
+
+    fn set_arguments(&mut self, arguments: Vec<String>) {
+        let value = Data::List {
+            value: arguments
+                .into_iter()
+                .map(|value| Data::Text { value })
+                .collect(),
+            sub_type: new_node_vec(Data::Type {
+                name: "text".to_string(),
+            }),
+        };
+        self.memory.declare(
+            "arguments",
+            Variable {
+                data_type: [type_node(&value)].to_vec(),
+                value,
+            },
+        );
+    }
+
+    fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.random.set(seed);
+    }
+
+    // splitmix64, small and good enough for simulations but not for cryptography
+    fn next_random(&self) -> u64 {
+        let state = self.random.get().wrapping_add(0x9e3779b97f4a7c15);
+        self.random.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in `0..bound` without modulo bias
+    fn random_below(&self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_random();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+
     fn init_memory(&mut self) {
-        let lib: Vec<(&str, fn(&Vec<Data>) -> Data)> = [
-            ("eq", eq as fn(&Vec<Data>) -> Data),
+        let lib: Vec<(&str, NativeFunction)> = [
+            ("eq", eq as NativeFunction),
             ("lt", lt),
             ("nand", nand),
             ("add", add),
             ("multiply", multiply),
-            ("raise", multiply),
+            ("power", power),
             ("floor", floor),
             ("join", join),
             ("push", push),
             ("pop", pop),
+            ("get", get),
+            ("put", put),
+            ("has", has),
+            ("length", length),
+            ("keys", keys),
+            ("values", values),
+            ("delete", delete),
+            ("assert", assert),
+            ("assert_equal", assert_equal),
+            ("is_null", is_null),
+            ("env_var", env_var),
+            ("exit", exit),
+            ("format_date", format_date),
+            ("parse_date", parse_date),
+            ("parse_json", parse_json),
+            ("to_json", to_json),
         ]
         .to_vec();
         for tuple in lib {
-            self.memory.insert(
-                tuple.0.to_string(),
+            self.memory.declare(
+                tuple.0,
+                Variable {
+                    value: Data::KraberFunction {
+                        body: Native(tuple.1),
+                    },
+                    data_type: new_node_vec(Data::Type {
+                        name: "kraberfunction".to_string(),
+                    }),
+                },
+            );
+        }
+        let methods: Vec<(&str, NativeMethod)> = [
+            ("map", map as NativeMethod),
+            ("filter", filter),
+            ("print", print),
+            ("print_line", print_line),
+            ("debug", debug),
+            ("read_line", read_line),
+            ("read_all", read_all),
+            ("read_lines", read_lines),
+            ("read_file", read_file),
+            ("write_file", write_file),
+            ("append_file", append_file),
+            ("list_dir", list_dir),
+            ("exists", exists),
+            ("delete_file", delete_file),
+            ("seed", seed),
+            ("random_float", random_float),
+            ("random_integer", random_integer),
+            ("shuffle", shuffle),
+            ("choose", choose),
+            ("now", now),
+            ("monotonic", monotonic),
+            ("sleep", sleep),
+        ]
+        .to_vec();
+        self.set_arguments([].to_vec());
+        self.memory.declare(
+            "error",
+            Variable {
+                value: KraberError {
+                    kind: "raised".to_string(),
+                    message: "".to_string(),
+                    line: 0,
+                }
+                .to_record(),
+                data_type: new_node_vec(Data::Type {
+                    name: "record".to_string(),
+                }),
+            },
+        );
+        for tuple in methods {
+            self.memory.declare(
+                tuple.0,
                 Variable {
-                    value: Data::KraberFunction { body: tuple.1 },
+                    value: Data::KraberMethod {
+                        body: Native(tuple.1),
+                    },
                     data_type: new_node_vec(Data::Type {
                         name: "kraberfunction".to_string(),
                     }),
@@ -834,129 +3264,260 @@ impl Interpreter {
         }
     }
 
+    fn eval_arguments(&mut self, nodes: &[Node]) -> Vec<Data> {
+        nodes
+            .iter()
+            .map(|x| {
+                let expression = Node {
+                    id: 0,
+                    data: Data::Expression,
+                    nodes: [x.clone()].to_vec(),
+                };
+                self.eval_expression(expression)
+            })
+            .collect()
+    }
+
+    fn eval_call_arguments(&mut self, nodes: &[Node]) -> (Vec<Data>, Vec<(String, Data)>) {
+        let (named, positional): (Vec<Node>, Vec<Node>) = nodes
+            .iter()
+            .cloned()
+            .partition(|x| matches!(x.data, Data::Argument { .. }));
+        let args = self.eval_arguments(&positional);
+        let named: Vec<(String, Data)> = named
+            .into_iter()
+            .map(|x| match &x.data {
+                Data::Argument { name } => {
+                    (name.to_string(), self.eval_arguments(&x.nodes)[0].clone())
+                }
+                _ => {
+                    panic!("expected Data::Argument");
+                }
+            })
+            .collect();
+        (args, named)
+    }
+
     fn eval_expression(&mut self, expression: Node) -> Data {
         return match &expression.nodes[0].data {
-            Data::Identifier { name } => {
-                let var = self.memory.get(&name.clone()).unwrap().clone();
-                let data: Data = match &var.value {
-                    Data::KraberFunction { body } => {
-                        let args: Vec<Data> = expression.nodes[0]
-                            .nodes
-                            .iter()
-                            .map(|x| {
-                                if matches!(&x.data, Data::Identifier { name: _ }) {
-                                    let mut nodes: Vec<Node> = Vec::new();
-                                    nodes.push(x.clone());
-                                    let expression = Node {
-                                        id: 0,
-                                        data: Data::Expression,
-                                        nodes,
-                                    };
-                                    self.eval_expression(expression)
-                                } else {
-                                    x.data.clone()
-                                }
-                            })
-                            .collect();
-                        body(&args)
-                    }
-                    Data::Function {
-                        body,
-                        params,
-                        param_types,
-                        return_types,
-                    } => {
-                        let args: Vec<Data> = expression.nodes[0]
-                            .nodes
-                            .iter()
-                            .map(|x| {
-                                if matches!(&x.data, Data::Identifier { name: _ }) {
-                                    let mut nodes: Vec<Node> = Vec::new();
-                                    nodes.push(x.clone());
-                                    let expression = Node {
-                                        id: 0,
-                                        data: Data::Expression,
-                                        nodes,
-                                    };
-                                    self.eval_expression(expression)
-                                } else {
-                                    x.data.clone()
-                                }
-                            })
-                            .collect();
-                        let tree = Tree {
-                            root: Node {
-                                id: 0,
-                                data: Data::Main,
-                                nodes: body.to_vec(),
-                            },
-                        };
-                        let mut memory = self.memory.clone();
-                        let mut locals: Vec<String> = Vec::new();
-                        for i in 0..params.len() {
-                            let param = &params[i];
-                            let param_type = &param_types[i];
-                            locals.push(param.to_string());
-                            memory.insert(
-                                param.to_string(),
-                                Variable {
-                                    value: args[i].clone(),
-                                    data_type: new_node_vec(param_type.clone()),
-                                },
-                            );
-                        }
-                        memory.insert(
-                            "return".to_string(),
-                            Variable {
-                                value: Data::Null,
-                                data_type: new_node_vec(return_types[0].clone()),
-                            },
-                        );
-                        let mut sub = Interpreter {
-                            tree,
-                            memory,
-                            locals,
-                        };
-                        sub.interpret();
-                        sub.filter_memory();
-                        self.memory = sub.memory.clone();
-                        sub.memory.get("return").unwrap().value.clone()
-                    }
-                    _ => var.value.clone(),
-                };
-                data
+            Data::Identifier { name } => self.lookup(name).value,
+            Data::Call { name } => {
+                let var = self.lookup(name);
+                if !is_callable(&var.value) {
+                    fail("type", format!("{name} is not a function"));
+                }
+                let (args, named) = self.eval_call_arguments(&expression.nodes[0].nodes);
+                self.call_function(var.value, args, named)
             }
             Data::FunctionContainer {
                 params,
                 param_types,
                 return_types,
+                defaults,
+                variadic,
             } => Data::Function {
                 params: params.to_vec(),
                 param_types: param_types.to_vec(),
+                defaults: defaults.to_vec(),
+                variadic: *variadic,
                 body: expression.nodes[0].nodes.clone(),
                 return_types: return_types.to_vec(),
+                environment: self.memory.clone(),
+            },
+            Data::Map { value: _, sub_type } => {
+                let args = self.eval_arguments(&expression.nodes[0].nodes);
+                if !args.len().is_multiple_of(2) {
+                    fail("runtime", "expected a value for every key".to_string());
+                }
+                let mut entries: Vec<(Data, Data)> = Vec::new();
+                for pair in args.chunks(2) {
+                    put_entry(&mut entries, sub_type, &pair[0], &pair[1]);
+                }
+                Data::Map {
+                    value: entries,
+                    sub_type: sub_type.to_vec(),
+                }
+            }
+            Data::Tuple { value: _ } => Data::Tuple {
+                value: self.eval_arguments(&expression.nodes[0].nodes),
             },
             _ => expression.nodes[0].data.clone(),
         };
     }
 
+    fn call_function(
+        &mut self,
+        function: Data,
+        args: Vec<Data>,
+        named: Vec<(String, Data)>,
+    ) -> Data {
+        if !named.is_empty() && !matches!(function, Data::Function { .. }) {
+            fail(
+                "arity",
+                "only user defined functions accept named arguments".to_string(),
+            );
+        }
+        match function {
+            Data::KraberFunction { body } => (body.0)(&args),
+            Data::KraberMethod { body } => (body.0)(self, &args),
+            Data::Function {
+                ref return_types, ..
+            } => {
+                let data_type = return_type_nodes(return_types);
+                let mut value = self.invoke(function, args, named);
+                // tail calls come back as a pending call and are run here
+                // instead of nesting another interpreter on the stack
+                while let Data::TailCall {
+                    function,
+                    args,
+                    named,
+                } = value
+                {
+                    value = self.invoke(*function, args, named);
+                }
+                cast(
+                    &mut value,
+                    Variable {
+                        value: Data::Null,
+                        data_type,
+                    },
+                );
+                value
+            }
+            Data::Variant { name, tag, fields } => {
+                expect_arguments(&args, fields.len());
+                Data::Variant {
+                    name,
+                    tag,
+                    fields: fields
+                        .into_iter()
+                        .zip(args)
+                        .map(|(field, mut value)| {
+                            let data_type = cast(&mut value, field.1);
+                            (field.0, Variable { value, data_type })
+                        })
+                        .collect(),
+                }
+            }
+            _ => {
+                fail(
+                    "type",
+                    format!("expected a function but got {:#?}", function),
+                );
+            }
+        }
+    }
+
+    fn invoke(&mut self, function: Data, args: Vec<Data>, named: Vec<(String, Data)>) -> Data {
+        match function {
+            Data::Function {
+                body,
+                params,
+                param_types,
+                return_types,
+                defaults,
+                variadic,
+                environment,
+            } => {
+                let mut args = args;
+                if args.len() > params.len() && !variadic {
+                    fail(
+                        "arity",
+                        format!(
+                            "expected at most {} arguments but received {}",
+                            params.len(),
+                            args.len()
+                        ),
+                    );
+                }
+                let mut slots: Vec<Option<Data>> = params.iter().map(|_| None).collect();
+                if variadic {
+                    let sub_type = param_types.last().unwrap().nodes.clone();
+                    let rest = args.split_off(args.len().min(params.len() - 1));
+                    let value = rest
+                        .into_iter()
+                        .map(|mut x| {
+                            cast(
+                                &mut x,
+                                Variable {
+                                    value: Data::Null,
+                                    data_type: sub_type.clone(),
+                                },
+                            );
+                            x
+                        })
+                        .collect();
+                    slots[params.len() - 1] = Some(Data::List { value, sub_type });
+                }
+                for (i, arg) in args.into_iter().enumerate() {
+                    slots[i] = Some(arg);
+                }
+                for (name, arg) in named {
+                    match params.iter().position(|x| *x == name) {
+                        Some(i) => {
+                            if slots[i].is_some() {
+                                fail("arity", format!("received {name} twice"));
+                            }
+                            slots[i] = Some(arg);
+                        }
+                        None => {
+                            fail("arity", format!("no parameter named {name}"));
+                        }
+                    }
+                }
+                if self.depth >= self.max_depth {
+                    fail("stack", "stack overflow".to_string());
+                }
+                let mut sub = self.sub_interpreter(body, Environment::new(Some(environment)));
+                sub.depth += 1;
+                sub.tail_calls = true;
+                sub.returned = Rc::new(Cell::new(false));
+                for i in 0..params.len() {
+                    let param = &params[i];
+                    // defaults are evaluated per call and can refer to earlier parameters
+                    let mut value = match (slots[i].take(), &defaults[i]) {
+                        (Some(value), _) => value,
+                        (None, Some(default)) => sub.eval_expression(default.clone()),
+                        (None, None) => {
+                            fail("arity", format!("missing argument for parameter {param}"));
+                        }
+                    };
+                    let data_type = cast(
+                        &mut value,
+                        Variable {
+                            value: Data::Null,
+                            data_type: [param_types[i].clone()].to_vec(),
+                        },
+                    );
+                    sub.memory.declare(param, Variable { value, data_type });
+                }
+                sub.memory.declare(
+                    "return",
+                    Variable {
+                        value: Data::Null,
+                        data_type: return_type_nodes(&return_types),
+                    },
+                );
+                let line = LINE.get();
+                sub.interpret_body();
+                LINE.set(line);
+                sub.memory.get("return").unwrap().value
+            }
+            _ => {
+                fail(
+                    "type",
+                    format!("expected a function but got {:#?}", function),
+                );
+            }
+        }
+    }
+
     fn loop_while(&mut self, expression: Node, body: Vec<Node>) -> bool {
-        let tree = Tree {
-            root: Node {
-                id: 0,
-                data: Data::Main,
-                nodes: body,
-            },
-        };
-        let mut sub = Interpreter {
-            tree,
-            memory: self.memory.clone(),
-            locals: Vec::new(),
-        };
+        let mut sub = self.sub_interpreter(body, Environment::new(Some(self.memory.clone())));
         let mut condition: bool = match self.eval_expression(expression.clone()) {
             Data::Boolean { value } => value,
             _ => {
-                panic!("expected boolean");
+                fail("type", "expected boolean".to_string());
             }
         };
         while condition {
@@ -964,78 +3525,366 @@ impl Interpreter {
             condition = match sub.eval_expression(expression.clone()) {
                 Data::Boolean { value } => value,
                 _ => {
-                    panic!("expected boolean");
+                    fail("type", "expected boolean".to_string());
                 }
             };
-            match sub.memory.get("return") {
-                Some(variable) => {
-                    if variable.value != Data::Null {
-                        self.memory = sub.memory.clone();
-                        return true;
-                    }
-                }
-                None => {}
+            if sub.returned.get() {
+                return true;
             }
         }
-        self.memory = sub.filter_memory().to_owned();
         false
     }
 
+    fn match_case(&mut self, expression: Node, cases: Vec<Node>) -> bool {
+        let (tag, fields) = match self.eval_expression(expression) {
+            Data::Variant {
+                name: _,
+                tag,
+                fields,
+            } => (tag, fields),
+            value => {
+                fail(
+                    "type",
+                    format!("expected Data::Variant but got {:#?}", value),
+                );
+            }
+        };
+        let case = match cases.into_iter().find(|x| match &x.data {
+            Data::Case { tag: case_tag, .. } => *case_tag == tag || case_tag == "_",
+            _ => false,
+        }) {
+            Some(x) => x,
+            None => {
+                fail("runtime", format!("no case matches {tag}"));
+            }
+        };
+        let memory = Environment::new(Some(self.memory.clone()));
+        if let Data::Case { tag: _, bindings } = &case.data {
+            for (binding, field) in bindings.iter().zip(fields) {
+                memory.declare(binding, field.1);
+            }
+        }
+        let mut sub = self.sub_interpreter(case.nodes, memory);
+        sub.interpret();
+        sub.returned.get()
+    }
+
+    fn try_catch(&mut self, nodes: Vec<Node>) -> bool {
+        let (handlers, body): (Vec<Node>, Vec<Node>) = nodes
+            .into_iter()
+            .partition(|x| matches!(x.data, Data::Catch { .. } | Data::Finally));
+        let catch = handlers
+            .iter()
+            .find(|x| matches!(x.data, Data::Catch { .. }));
+        let finally = handlers.iter().find(|x| x.data == Data::Finally);
+        let mut sub = self.sub_interpreter(body, Environment::new(Some(self.memory.clone())));
+        // a call returned from inside try has to run before leaving it
+        sub.tail_calls = false;
+        let mut result = attempt(|| sub.interpret());
+        if let (Err(error), Some(catch)) = (&result, catch) {
+            let memory = Environment::new(Some(self.memory.clone()));
+            if let Data::Catch { name } = &catch.data {
+                memory.declare(
+                    name,
+                    Variable {
+                        value: error.to_record(),
+                        data_type: new_node_vec(Data::Type {
+                            name: "error".to_string(),
+                        }),
+                    },
+                );
+            }
+            let mut sub = self.sub_interpreter(catch.nodes.clone(), memory);
+            if finally.is_some() {
+                sub.tail_calls = false;
+                result = attempt(|| sub.interpret());
+            } else {
+                sub.interpret();
+                result = Ok(());
+            }
+        }
+        if let Some(finally) = finally {
+            let mut sub = self.sub_interpreter(
+                finally.nodes.clone(),
+                Environment::new(Some(self.memory.clone())),
+            );
+            sub.interpret();
+        }
+        if let Err(error) = result {
+            // raised again so that an enclosing try or the top level sees it
+            panic::panic_any(error);
+        }
+        self.returned.get()
+    }
+
+    fn set_file(&mut self, path: &str) {
+        self.file = PathBuf::from(path);
+        // so that a file importing the main file is reported as a cycle
+        if let Ok(path) = fs::canonicalize(path) {
+            self.modules.borrow_mut().insert(path, None);
+        }
+    }
+
+    fn import(&mut self, path: &str, name: &str) -> Data {
+        let source = STD.iter().find(|x| x.0 == path).map(|x| x.1);
+        let path = match source {
+            Some(_) => PathBuf::from(path),
+            None => {
+                let directory = self.file.parent().unwrap_or(Path::new("."));
+                let path = match fs::canonicalize(directory.join(path)) {
+                    Ok(path) => path,
+                    Err(error) => fail("import", format!("{path}: {error}")),
+                };
+                // importing runs a file, so it needs the same access as reading it
+                self.capabilities.check(&path.to_string_lossy(), false)
+            }
+        };
+        let cached = self.modules.borrow().get(&path).cloned();
+        let fields = match cached {
+            Some(Some(fields)) => fields,
+            Some(None) => fail(
+                "import",
+                format!("{} is imported in a cycle", path.display()),
+            ),
+            None => {
+                self.modules.borrow_mut().insert(path.clone(), None);
+                let mut fields = Vec::new();
+                let result = attempt(|| fields = self.run_module(&path, source));
+                if let Err(error) = result {
+                    // forgotten so that importing it again fails the same way instead of as a cycle
+                    self.modules.borrow_mut().remove(&path);
+                    panic::panic_any(error);
+                }
+                self.modules.borrow_mut().insert(path, Some(fields.clone()));
+                fields
+            }
+        };
+        // a module is a record of what its file exports
+        Data::Record {
+            name: name.to_string(),
+            fields,
+        }
+    }
+
+    fn run_module(&self, path: &Path, source: Option<&str>) -> Vec<(String, Variable)> {
+        let line = LINE.get();
+        let tree = match source {
+            Some(code) => parse(code.to_string()),
+            None => load(&path.to_string_lossy()),
+        };
+        let mut sub = self.sub_interpreter(tree.root.nodes, Environment::new(None));
+        sub.file = path.to_path_buf();
+        sub.init_memory();
+        sub.interpret_body();
+        LINE.set(line);
+        sub.tree
+            .root
+            .nodes
+            .iter()
+            .filter_map(|x| match &x.data {
+                Data::Export { name } => Some((name.to_string(), sub.lookup(name))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn define_function(&mut self, node: &Node) {
+        if let Data::Identifier { name } = &node.nodes[0].data {
+            let expression = Node {
+                id: 0,
+                data: Data::Expression,
+                nodes: [node.nodes[1].clone()].to_vec(),
+            };
+            let value = self.eval_expression(expression);
+            self.memory.declare(
+                name,
+                Variable {
+                    data_type: [type_node(&value)].to_vec(),
+                    value,
+                },
+            );
+        }
+    }
+
+    // named functions of a program or function body are declared before anything
+    // runs so they can call each other
+    fn interpret_body(&mut self) {
+        for node in self.tree.root.nodes.clone() {
+            if node.data == Data::Define
+                && matches!(node.nodes[1].data, Data::FunctionContainer { .. })
+            {
+                self.define_function(&node);
+            }
+        }
+        self.interpret();
+    }
+
     fn interpret(&mut self) {
         for node in self.tree.root.nodes.clone() {
             match &node.data {
+                Data::Line { number } => {
+                    LINE.set(*number);
+                }
+                Data::Raise => {
+                    let expression = Node {
+                        id: 0,
+                        data: Data::Expression,
+                        nodes: node.nodes.clone(),
+                    };
+                    let value = match self.eval_expression(expression) {
+                        // `raise ("x")` raises the text in the parentheses
+                        Data::Tuple { mut value } if value.len() == 1 => value.remove(0),
+                        value => value,
+                    };
+                    match value {
+                        Data::Text { value } => fail("raised", value),
+                        Data::Record { name, fields } if name == "error" => {
+                            panic::panic_any(KraberError::from_record(&fields))
+                        }
+                        value => {
+                            fail(
+                                "type",
+                                format!("expected text or error to raise but got {:#?}", value),
+                            );
+                        }
+                    }
+                }
+                Data::Import { path, name } => {
+                    let value = self.import(path, name);
+                    self.memory.declare(
+                        name,
+                        Variable {
+                            data_type: [type_node(&value)].to_vec(),
+                            value,
+                        },
+                    );
+                }
+                Data::Try => {
+                    let returned = self.try_catch(node.nodes.clone());
+                    if returned {
+                        return;
+                    }
+                }
                 Data::While => {
                     if self.loop_while(node.nodes[0].clone(), node.nodes[1..].to_vec().clone()) {
                         return;
                     }
                 }
                 Data::Return => {
+                    let variable = self.memory.get("return").unwrap();
+                    if let (true, Data::Call { name }) = (self.tail_calls, &node.nodes[0].data) {
+                        let function = self.lookup(name).value;
+                        if matches!(function, Data::Function { .. }) {
+                            let (args, named) = self.eval_call_arguments(&node.nodes[0].nodes);
+                            let value = Data::TailCall {
+                                function: Box::new(function),
+                                args,
+                                named,
+                            };
+                            self.memory.assign(
+                                "return",
+                                Variable {
+                                    value,
+                                    data_type: variable.data_type,
+                                },
+                            );
+                            self.returned.set(true);
+                            return;
+                        }
+                    }
                     let expression = Node {
                         id: 0,
                         data: Data::Expression,
                         nodes: node.nodes.clone(),
                     };
-                    let value = self.eval_expression(expression);
-                    self.memory.insert(
-                        "return".to_string(),
-                        Variable {
-                            value,
-                            data_type: self.memory.get("return").unwrap().data_type.clone(),
-                        },
-                    );
+                    let mut value = self.eval_expression(expression);
+                    let data_type = cast(&mut value, variable);
+                    self.memory.assign("return", Variable { value, data_type });
+                    self.returned.set(true);
                     return;
                 }
                 Data::Declare => {
                     match &node.nodes[0].data {
                         Data::Identifier { name } => {
                             let data_type = node.nodes[1..].to_vec();
-                            self.memory.insert(
-                                name.clone(),
+                            self.memory.declare(
+                                name,
                                 Variable {
-                                    value: match &data_type[0].data {
-                                        Data::Type { name } => match name.as_str() {
-                                            "list" => Data::List {
-                                                value: [].to_vec(),
-                                                sub_type: data_type[0].nodes.clone(),
-                                            }, // TODO: fix `sub_type`
-                                            _ => Data::Null,
-                                        },
-                                        _ => {
-                                            panic!("expected Data::Type");
-                                        }
-                                    },
+                                    value: self.default_value(&data_type),
                                     data_type,
                                 },
                             );
-                            self.locals.push(name.to_string());
                         }
                         _ => {}
                     };
                 }
+                Data::Define => {
+                    if matches!(node.nodes[1].data, Data::FunctionContainer { .. }) {
+                        let hoisted = match &node.nodes[0].data {
+                            Data::Identifier { name } => {
+                                self.memory.0.borrow().variables.contains_key(name)
+                            }
+                            _ => false,
+                        };
+                        // other blocks declare a named function when they reach it
+                        if !hoisted {
+                            self.define_function(&node);
+                        }
+                        continue;
+                    }
+                    match &node.nodes[0].data {
+                        Data::Identifier { name } => {
+                            match expect_type(&node.nodes[1].data).as_str() {
+                                "enum" => {
+                                    for variant in &node.nodes[1].nodes {
+                                        let tag = match &variant.data {
+                                            Data::Identifier { name } => name.to_string(),
+                                            _ => {
+                                                panic!("expected Data::Identifier");
+                                            }
+                                        };
+                                        self.memory.declare(
+                                            &tag,
+                                            Variable {
+                                                value: Data::Variant {
+                                                    name: name.to_string(),
+                                                    tag: tag.clone(),
+                                                    fields: self.build_fields(&variant.nodes),
+                                                },
+                                                data_type: new_node_vec(Data::Type {
+                                                    name: name.to_string(),
+                                                }),
+                                            },
+                                        );
+                                    }
+                                }
+                                _ => {
+                                    self.memory.declare(
+                                        name,
+                                        Variable {
+                                            value: Data::Record {
+                                                name: name.to_string(),
+                                                fields: self.build_fields(&node.nodes[1].nodes),
+                                            },
+                                            data_type: new_node_vec(node.nodes[1].data.clone()),
+                                        },
+                                    );
+                                }
+                            }
+                        }
+                        _ => {}
+                    };
+                }
+                Data::Match => {
+                    let returned = self.match_case(node.nodes[0].clone(), node.nodes[1..].to_vec());
+                    if returned {
+                        return;
+                    }
+                }
                 Data::Assign => {
                     match &node.nodes[0].data {
                         Data::Identifier { name } => {
-                            let variable = self.memory.get(&name.clone()).unwrap().clone();
+                            let variable = self.lookup(name);
                             let mut expression = Node {
                                 id: 0,
                                 data: Data::Expression,
@@ -1044,59 +3893,185 @@ impl Interpreter {
                             expression.nodes.push(node.nodes[1].clone());
                             let mut expression_value = self.eval_expression(expression);
                             let data_type = cast(&mut expression_value, variable);
-                            self.memory.insert(
-                                name.clone(),
+                            self.store(
+                                name,
                                 Variable {
                                     value: expression_value,
                                     data_type,
                                 },
                             );
                         }
+                        Data::Tuple { value: _ } => {
+                            let expression = Node {
+                                id: 0,
+                                data: Data::Expression,
+                                nodes: [node.nodes[1].clone()].to_vec(),
+                            };
+                            let values = match self.eval_expression(expression) {
+                                Data::Tuple { value } => value,
+                                value => {
+                                    fail(
+                                        "type",
+                                        format!("expected Data::Tuple but got {:#?}", value),
+                                    );
+                                }
+                            };
+                            if values.len() != node.nodes[0].nodes.len() {
+                                fail(
+                                    "type",
+                                    format!(
+                                        "expected {} values but received {}",
+                                        node.nodes[0].nodes.len(),
+                                        values.len()
+                                    ),
+                                );
+                            }
+                            for (target, mut value) in node.nodes[0].nodes.iter().zip(values) {
+                                if let Data::Identifier { name } = &target.data {
+                                    let data_type = cast(&mut value, self.lookup(name));
+                                    self.store(name, Variable { value, data_type });
+                                }
+                            }
+                        }
                         _ => {}
                     };
                 }
                 Data::Text { value } => {
-                    println!("{}", value); // implicit print
+                    self.write_line(value); // implicit print
                 }
                 Data::Identifier { name } => {
-                    let x = &self.memory.get(&name.clone()).unwrap().value;
-                    match x {
-                        Data::Type { name } => println!("{name}"),
-                        Data::Null => println!("null"),
-                        Data::Whole { value } => println!("{value}"),
-                        Data::Integer { value } => println!("{value}"),
-                        Data::Float { value } => println!("{value}"),
-                        Data::Boolean { value } => println!("{value}"),
-                        Data::Text { value } => println!("{value}"),
-                        Data::KraberFunction { body: _ } => {
-                            let mut expression = Node {
-                                id: 0,
-                                data: Data::Expression,
-                                nodes: Vec::new(),
-                            };
-                            expression.nodes.push(node.clone());
-                            println!("{}", self.eval_expression(expression))
-                        }
-                        _ => {}
+                    let value = self.lookup(name).value;
+                    self.write_line(&value.to_string()); // implicit print
+                }
+                Data::Whole { .. }
+                | Data::Integer { .. }
+                | Data::Float { .. }
+                | Data::Boolean { .. }
+                | Data::Tuple { .. }
+                | Data::Map { .. }
+                | Data::FunctionContainer { .. } => {
+                    let expression = Node {
+                        id: 0,
+                        data: Data::Expression,
+                        nodes: [node.clone()].to_vec(),
+                    };
+                    let value = self.eval_expression(expression);
+                    self.write_line(&value.to_string()); // implicit print
+                }
+                Data::Call { name: _ } => {
+                    let expression = Node {
+                        id: 0,
+                        data: Data::Expression,
+                        nodes: [node.clone()].to_vec(),
                     };
+                    let value = self.eval_expression(expression);
+                    if value != Data::Null {
+                        self.write_line(&value.to_string()); // implicit print
+                    }
                 }
                 _ => {}
             };
         }
     }
 
-    fn filter_memory(&mut self) -> &mut HashMap<String, Variable> {
-        for local in &self.locals {
-            if self.memory.contains_key(local) {
-                self.memory.remove(local);
+    fn build_fields(&self, nodes: &[Node]) -> Vec<(String, Variable)> {
+        nodes
+            .iter()
+            .map(|field| {
+                let data_type = field.nodes[1..].to_vec();
+                let field_name = match &field.nodes[0].data {
+                    Data::Identifier { name } => name.to_string(),
+                    _ => {
+                        panic!("expected Data::Identifier");
+                    }
+                };
+                (
+                    field_name,
+                    Variable {
+                        value: self.default_value(&data_type),
+                        data_type,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn lookup(&self, name: &str) -> Variable {
+        let mut path = name.split('.');
+        let root = path.next().unwrap();
+        let mut variable = self
+            .memory
+            .get(root)
+            .unwrap_or_else(|| fail("runtime", format!("{root} is not declared")));
+        for field in path {
+            variable = match &variable.value {
+                Data::Record { name, fields } => match fields.iter().find(|x| x.0 == field) {
+                    Some(x) => x.1.clone(),
+                    None => {
+                        fail("runtime", format!("record {name} has no field {field}"));
+                    }
+                },
+                _ => {
+                    fail(
+                        "type",
+                        format!("expected Data::Record but got {:#?}", variable.value),
+                    );
+                }
+            };
+        }
+        variable
+    }
+
+    fn store(&mut self, name: &str, variable: Variable) {
+        match name.split_once('.') {
+            Some((root, path)) => {
+                let mut record = self
+                    .memory
+                    .get(root)
+                    .unwrap_or_else(|| fail("runtime", format!("{root} is not declared")));
+                set_field(&mut record.value, path, variable);
+                self.memory.assign(root, record);
+            }
+            None => {
+                self.memory.assign(name, variable);
+            }
+        }
+    }
+
+    fn default_value(&self, data_type: &[Node]) -> Data {
+        match &data_type[0].data {
+            Data::Type { name } => match name.as_str() {
+                "list" => Data::List {
+                    value: [].to_vec(),
+                    sub_type: data_type[0].nodes.clone(),
+                }, // TODO: fix `sub_type`
+                "map" => Data::Map {
+                    value: [].to_vec(),
+                    sub_type: data_type[0].nodes.clone(),
+                },
+                _ => match self.memory.get(name) {
+                    Some(Variable {
+                        value: Data::Record { name, fields },
+                        data_type,
+                    }) if expect_type(&data_type[0].data) == "record" => Data::Record {
+                        name: name.to_string(),
+                        fields: fields.to_vec(),
+                    },
+                    _ => Data::Null,
+                },
+            },
+            _ => {
+                panic!("expected Data::Type");
             }
         }
-        &mut self.memory
     }
 }
 
 fn cast(expression_value: &mut Data, variable: Variable) -> Vec<Node> {
     let data_types = variable.data_type;
+    if *expression_value == Data::Null {
+        return data_types; // variables of every type start out as null
+    }
     let type_name = stringify_enum(&*expression_value);
     for data_type in &data_types {
         match &data_type.data {
@@ -1106,25 +4081,41 @@ fn cast(expression_value: &mut Data, variable: Variable) -> Vec<Node> {
                         Data::Float { value: float } => match name.as_str() {
                             "whole" => {
                                 if float < 0.0 {
-                                    panic!("could not cast negative float to whole");
+                                    fail(
+                                        "type",
+                                        "could not cast negative float to whole".to_string(),
+                                    );
+                                }
+                                // `as` would saturate instead of failing
+                                if !(0.0..usize::MAX as f64).contains(&float) {
+                                    fail("type", format!("{float} is too large for whole"));
                                 }
                                 *expression_value = Data::Whole {
                                     value: float as usize,
                                 };
                             }
                             "integer" => {
+                                if !(isize::MIN as f64..isize::MAX as f64).contains(&float) {
+                                    fail("type", format!("{float} does not fit in integer"));
+                                }
                                 *expression_value = Data::Integer {
                                     value: float as isize,
                                 };
                             }
                             _ => {
-                                panic!("could not cast {type_name:#?} to {name:#?}");
+                                fail(
+                                    "type",
+                                    format!("could not cast {type_name:#?} to {name:#?}"),
+                                );
                             }
                         },
                         Data::Integer { value: integer } => match name.as_str() {
                             "whole" => {
                                 if integer < 0 {
-                                    panic!("could not cast negative integer to whole");
+                                    fail(
+                                        "type",
+                                        "could not cast negative integer to whole".to_string(),
+                                    );
                                 }
                                 *expression_value = Data::Whole {
                                     value: integer as usize,
@@ -1136,7 +4127,10 @@ fn cast(expression_value: &mut Data, variable: Variable) -> Vec<Node> {
                                 };
                             }
                             _ => {
-                                panic!("could not cast {type_name:#?} to {name:#?}");
+                                fail(
+                                    "type",
+                                    format!("could not cast {type_name:#?} to {name:#?}"),
+                                );
                             }
                         },
                         Data::Whole { value: whole } => match name.as_str() {
@@ -1151,18 +4145,36 @@ fn cast(expression_value: &mut Data, variable: Variable) -> Vec<Node> {
                                 };
                             }
                             _ => {
-                                panic!("could not cast {type_name:#?} to {name:#?}");
+                                fail(
+                                    "type",
+                                    format!("could not cast {type_name:#?} to {name:#?}"),
+                                );
                             }
                         },
+                        // builtins carry no signature so they only satisfy a bare `function`
+                        Data::KraberFunction { body: _ } | Data::KraberMethod { body: _ }
+                            if name == "function" && data_type.nodes.is_empty() => {}
+                        // a bare `record` accepts a record of any shape, e.g. one read from json
+                        Data::Record { .. } if name == "record" => {}
                         _ => {
-                            panic!("could not cast {type_name:#?} to {name:#?}");
+                            fail(
+                                "type",
+                                format!("could not cast {type_name:#?} to {name:#?}"),
+                            );
                         }
                     }
                 } else if name == "list" {
                     match expression_value {
                         Data::List { value, sub_type } => {
-                            if !has_type(&data_types[0].nodes, sub_type[0].clone()) {
-                                panic!("oh shit");
+                            // a bare `list` accepts a list of any element type
+                            let declared = &data_types[0].nodes;
+                            // and an empty list fits every list type
+                            let sub_type = match value.is_empty() {
+                                true if !declared.is_empty() => declared,
+                                _ => sub_type,
+                            };
+                            if !declared.is_empty() && !has_type(declared, sub_type[0].clone()) {
+                                fail("type", "mismatched list types".to_string());
                             }
                             *expression_value = Data::List {
                                 value: value.to_vec(),
@@ -1170,7 +4182,58 @@ fn cast(expression_value: &mut Data, variable: Variable) -> Vec<Node> {
                             };
                         }
                         _ => {
-                            panic!("could not cast {type_name:#?} to {name:#?}");
+                            fail(
+                                "type",
+                                format!("could not cast {type_name:#?} to {name:#?}"),
+                            );
+                        }
+                    }
+                } else if name == "map" {
+                    if !has_type(&vec![data_type.clone()], type_node(expression_value)) {
+                        fail("type", "mismatched map types".to_string());
+                    }
+                } else if name == "function" {
+                    if !data_type.nodes.is_empty()
+                        && !has_type(&vec![data_type.clone()], type_node(expression_value))
+                    {
+                        fail("type", "mismatched function signature".to_string());
+                    }
+                } else if name == "tuple" {
+                    match expression_value {
+                        Data::Tuple { value } => {
+                            if value.len() != data_type.nodes.len() {
+                                fail(
+                                    "type",
+                                    format!(
+                                        "could not cast {type_name:#?} of {} values to {} values",
+                                        value.len(),
+                                        data_type.nodes.len()
+                                    ),
+                                );
+                            }
+                            for (element, element_type) in value.iter_mut().zip(&data_type.nodes) {
+                                cast(
+                                    element,
+                                    Variable {
+                                        value: Data::Null,
+                                        data_type: [element_type.clone()].to_vec(),
+                                    },
+                                );
+                            }
+                        }
+                        _ => {
+                            fail(
+                                "type",
+                                format!("could not cast {type_name:#?} to {name:#?}"),
+                            );
+                        }
+                    }
+                } else if let Data::Record { name: _, fields } = expression_value {
+                    for (field, variable) in fields {
+                        if variable.value != Data::Null
+                            && !has_type(&variable.data_type, type_node(&variable.value))
+                        {
+                            fail("type", format!("field {field} does not match its type"));
                         }
                     }
                 } // else do not mutate `expression_value`
@@ -1183,11 +4246,33 @@ fn cast(expression_value: &mut Data, variable: Variable) -> Vec<Node> {
     data_types
 }
 
+fn set_field(data: &mut Data, path: &str, variable: Variable) {
+    let (field, rest) = match path.split_once('.') {
+        Some((field, rest)) => (field, Some(rest)),
+        None => (path, None),
+    };
+    match data {
+        Data::Record { name, fields } => match fields.iter_mut().find(|x| x.0 == field) {
+            Some(x) => match rest {
+                Some(rest) => set_field(&mut x.1.value, rest, variable),
+                None => x.1 = variable,
+            },
+            None => {
+                fail("runtime", format!("record {name} has no field {field}"));
+            }
+        },
+        _ => {
+            fail("type", format!("expected Data::Record but got {:#?}", data));
+        }
+    }
+}
+
 fn create_lexer(code: String) -> Lexer {
     let character = code.chars().nth(0).expect("blank code string detected");
     Lexer {
         code,
         index: 0,
+        line: 1,
         character,
         end: false,
         letters: "abcdefghijklmnopqrstuvwxyz_".to_string(),
@@ -1199,6 +4284,14 @@ fn create_lexer(code: String) -> Lexer {
             "to".to_string(),
             "while".to_string(),
             "return".to_string(),
+            "define".to_string(),
+            "match".to_string(),
+            "case".to_string(),
+            "try".to_string(),
+            "catch".to_string(),
+            "finally".to_string(),
+            "import".to_string(),
+            "export".to_string(),
         ],
     }
 }
@@ -1210,34 +4303,317 @@ fn create_parser(tokens: Vec<Token>) -> Parser {
         tokens,
         token,
         end: false,
+        variants: HashMap::new(),
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("missing path to kraber file (cargo run -- <path>)");
-    }
-    let code = fs::read_to_string(&args[1]).expect("file not found");
-    // println!("{}", code);
-    let start_time = Instant::now();
-    let mut lexer = create_lexer(code);
-    let tokens = lexer.get_tokens();
-    /* for token in &tokens {
-        println!("{token}");
-    } */
-    let mut parser = create_parser(tokens);
-    let ast = parser.parse();
-    println!("{ast:#?}");
+fn create_interpreter(
+    tree: Tree,
+    input: Rc<RefCell<dyn BufRead>>,
+    output: Rc<RefCell<dyn Write>>,
+    capabilities: Capabilities,
+    max_depth: usize,
+) -> Interpreter {
     let mut interpreter = Interpreter {
-        tree: ast,
-        memory: HashMap::new(),
-        locals: Vec::new(),
+        tree,
+        memory: Environment::new(None),
+        input,
+        output,
+        capabilities: Rc::new(capabilities),
+        file: PathBuf::new(),
+        modules: Rc::new(RefCell::new(HashMap::new())),
+        depth: 0,
+        max_depth,
+        tail_calls: true,
+        returned: Rc::new(Cell::new(false)),
+        random: Rc::new(Cell::new(0)),
+        clock: Rc::new(SystemClock(Instant::now())),
     };
     interpreter.init_memory();
-    interpreter.interpret();
-    let elapsed = start_time.elapsed();
-    println!("{:#?}", elapsed);
-    let memory = interpreter.memory;
-    println!("{memory:#?}");
+    interpreter
+}
+
+// modules of the standard library, written in kraber and bundled into the binary
+const STD: [(&str, &str); 3] = [
+    ("std/list", include_str!("std/list.kraber")),
+    ("std/math", include_str!("std/math.kraber")),
+    ("std/text", include_str!("std/text.kraber")),
+];
+
+fn load(path: &str) -> Tree {
+    parse(fs::read_to_string(path).expect("file not found"))
+}
+
+fn parse(code: String) -> Tree {
+    let mut lexer = create_lexer(code);
+    let tokens = lexer.get_tokens();
+    let mut parser = create_parser(tokens);
+    parser.parse()
+}
+
+// rough upper bound of native stack used by one nested kraber call in a debug build
+const STACK_PER_CALL: usize = 64 * 1024;
+// keeps the stack reserved for the interpreter thread below about 640MB
+const MAX_DEPTH: usize = 10_000;
+
+fn run(
+    path: &str,
+    arguments: Vec<String>,
+    capabilities: Capabilities,
+    max_depth: usize,
+    seed: u64,
+    debug: bool,
+) {
+    let start_time = Instant::now();
+    let ast = load(path);
+    if debug {
+        println!("{ast:#?}");
+    }
+    let mut interpreter = create_interpreter(
+        ast,
+        Rc::new(RefCell::new(io::stdin().lock())),
+        Rc::new(RefCell::new(io::stdout())),
+        capabilities,
+        max_depth,
+    );
+    interpreter.set_arguments(arguments);
+    interpreter.set_file(path);
+    interpreter.set_seed(seed);
+    interpreter.interpret_body();
+    if debug {
+        let elapsed = start_time.elapsed();
+        println!("{:#?}", elapsed);
+        let memory = &interpreter.memory.0.borrow().variables;
+        println!("{memory:#?}");
+    }
+}
+
+// runs the `test_*` functions of every file in dir, or the whole file if it is named `test_*`
+fn run_tests(dir: &str, capabilities: Capabilities, max_depth: usize, seed: u64) -> bool {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .expect("test directory not found")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "kraber"))
+        .collect();
+    paths.sort();
+    let mut failures: Vec<(String, KraberError, String)> = Vec::new();
+    let mut passed = 0;
+    for path in paths {
+        let file = path.file_name().unwrap().to_string_lossy().to_string();
+        let path = path.to_string_lossy().to_string();
+        let mut tree = Tree::new();
+        LINE.set(0);
+        if let Err(error) = attempt(|| tree = load(&path)) {
+            println!("test {file} ... FAILED");
+            failures.push((file, error, "".to_string()));
+            continue;
+        }
+        let functions: Vec<String> = tree
+            .root
+            .nodes
+            .iter()
+            .filter(|x| {
+                x.data == Data::Define && matches!(x.nodes[1].data, Data::FunctionContainer { .. })
+            })
+            .filter_map(|x| match &x.nodes[0].data {
+                Data::Identifier { name } if name.starts_with("test_") => Some(name.to_string()),
+                _ => None,
+            })
+            .collect();
+        let mut tests: Vec<(String, Option<String>)> = functions
+            .into_iter()
+            .map(|name| (format!("{file}::{name}"), Some(name)))
+            .collect();
+        if tests.is_empty() && file.starts_with("test_") {
+            tests.push((file.to_string(), None));
+        }
+        for (test, function) in tests {
+            // every test gets a fresh interpreter so tests cannot affect each other
+            let output = Buffer::default();
+            let mut interpreter = create_interpreter(
+                tree.clone(),
+                Rc::new(RefCell::new(io::empty())),
+                Rc::new(RefCell::new(output.clone())),
+                capabilities.clone(),
+                max_depth,
+            );
+            interpreter.set_file(&path);
+            interpreter.set_seed(seed);
+            interpreter.set_clock(Rc::new(ManualClock::default()));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                attempt(|| {
+                    interpreter.interpret_body();
+                    if let Some(name) = function {
+                        let function = interpreter.lookup(&name).value;
+                        interpreter.call_function(function, [].to_vec(), [].to_vec());
+                    }
+                })
+            }))
+            .unwrap_or_else(|payload| match payload.downcast_ref::<Exit>() {
+                Some(Exit(0)) => Ok(()),
+                Some(Exit(code)) => Err(KraberError {
+                    kind: "exit".to_string(),
+                    message: format!("exited with code {code}"),
+                    line: LINE.get(),
+                }),
+                None => panic::resume_unwind(payload),
+            });
+            match result {
+                Ok(()) => {
+                    println!("test {test} ... ok");
+                    passed += 1;
+                }
+                Err(error) => {
+                    println!("test {test} ... FAILED");
+                    failures.push((test, error, output.text()));
+                }
+            }
+        }
+    }
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (test, error, output) in &failures {
+            match error.line {
+                0 => println!("\n{test} ({} error):\n{}", error.kind, error.message),
+                line => println!(
+                    "\n{test} ({} error on line {line}):\n{}",
+                    error.kind, error.message
+                ),
+            }
+            if !output.is_empty() {
+                print!("output:\n{output}");
+            }
+        }
+    }
+    println!(
+        "\ntest result: {}. {passed} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        failures.len()
+    );
+    failures.is_empty()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut commands: Vec<String> = Vec::new();
+    let mut max_depth: usize = 1000;
+    let mut debug = false;
+    let mut capabilities = Capabilities::default();
+    let mut seed = None;
+    let mut args = args[1..].iter();
+    for arg in args.by_ref() {
+        if arg == "--debug" {
+            debug = true; // dump the syntax tree, run time and memory
+        } else if let Some(path) = arg.strip_prefix("--allow-read=") {
+            Capabilities::allow(&mut capabilities.read, path);
+        } else if let Some(path) = arg.strip_prefix("--allow-write=") {
+            Capabilities::allow(&mut capabilities.write, path);
+        } else if let Some(value) = arg.strip_prefix("--max-depth=") {
+            max_depth = value.parse().expect("--max-depth expects a whole number");
+            if max_depth > MAX_DEPTH {
+                panic!("--max-depth can be at most {MAX_DEPTH}");
+            }
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            seed = Some(value.parse().expect("--seed expects a whole number"));
+        } else {
+            commands.push(arg.to_string());
+            break;
+        }
+    }
+    // everything after the path is passed on to the program
+    let arguments: Vec<String> = args.cloned().collect();
+    if commands.is_empty() {
+        panic!("missing path to kraber file (cargo run -- [--debug] [--max-depth=<n>] [--seed=<n>] [--allow-read=<dir>] [--allow-write=<dir>] <path> [arguments] | test <dir>)");
+    }
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let caught = TRYING.get() > 0 && info.payload().is::<KraberError>();
+        if caught || info.payload().is::<Exit>() {
+            return; // handled by a catch block or a deliberate exit
+        }
+        if LINE.get() == 0 {
+            hook(info); // not running a program yet
+            return;
+        }
+        let error = KraberError::from_payload(info.payload());
+        eprintln!(
+            "{} error on line {}: {}",
+            error.kind, error.line, error.message
+        );
+    }));
+    // the interpreter recurses natively, so give it enough stack to reach max_depth
+    // and report "stack overflow" itself instead of aborting
+    let interpreter = thread::Builder::new()
+        .stack_size((max_depth + 16) * STACK_PER_CALL)
+        .spawn(move || {
+            if commands[0] == "test" {
+                let dir = arguments.first().map_or("tests", |x| x.as_str());
+                // tests are reproducible unless asked for another seed
+                if !run_tests(dir, capabilities, max_depth, seed.unwrap_or(0)) {
+                    process::exit(1);
+                }
+            } else {
+                let seed = seed.unwrap_or_else(|| {
+                    let time = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    time.as_nanos() as u64
+                });
+                run(
+                    &commands[0],
+                    arguments,
+                    capabilities,
+                    max_depth,
+                    seed,
+                    debug,
+                );
+            }
+        })
+        .expect("could not start the interpreter");
+    if let Err(payload) = interpreter.join() {
+        match payload.downcast_ref::<Exit>() {
+            Some(Exit(code)) => process::exit(*code),
+            None => process::exit(101),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with_output(code: &str, output: Rc<RefCell<dyn Write>>) {
+        let mut interpreter = create_interpreter(
+            parse(code.to_string()),
+            Rc::new(RefCell::new(io::empty())),
+            output,
+            Capabilities::default(),
+            100,
+        );
+        interpreter.interpret_body();
+    }
+
+    #[test]
+    fn buffer_collects_printed_text() {
+        let output = Buffer::default();
+        run_with_output(
+            "print_line(\"hello\")\nprint(1 2)",
+            Rc::new(RefCell::new(output.clone())),
+        );
+        assert_eq!(output.text(), "hello\n12");
+    }
+
+    #[test]
+    fn callback_receives_printed_text() {
+        let received = Rc::new(RefCell::new(String::new()));
+        let sink = received.clone();
+        let callback = Callback(move |bytes: &[u8]| {
+            sink.borrow_mut().push_str(&String::from_utf8_lossy(bytes));
+        });
+        run_with_output(
+            "print_line(\"hello\")\nadd(1 2)",
+            Rc::new(RefCell::new(callback)),
+        );
+        assert_eq!(*received.borrow(), "hello\n3\n");
+    }
 }
@@ -0,0 +1,196 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+// runs every example program and compares its output with the `.expected` file next to it,
+// set KRABER_BLESS=1 to write the current output as the expected one instead,
+// an `.input` file next to a program is passed to it as standard input,
+// the lines of a `.flags` file as options before its path
+// and the lines of an `.args` file as its arguments,
+// a program that fails is expected to do so, its error and exit code are checked as well
+#[test]
+fn examples_match_expected_output() {
+    let bless = env::var_os("KRABER_BLESS").is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "kraber"))
+        .collect();
+    paths.sort();
+    let mut failures: Vec<String> = Vec::new();
+    for path in paths {
+        let input = match fs::File::open(path.with_extension("input")) {
+            Ok(file) => Stdio::from(file),
+            Err(_) => Stdio::null(),
+        };
+        let flags = fs::read_to_string(path.with_extension("flags")).unwrap_or_default();
+        let arguments = fs::read_to_string(path.with_extension("args")).unwrap_or_default();
+        let output = Command::new(env!("CARGO_BIN_EXE_kraber"))
+            .args(flags.lines())
+            .arg(&path)
+            .args(arguments.lines())
+            .stdin(input)
+            .output()
+            .unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let mut actual = String::from_utf8_lossy(&output.stdout).to_string();
+        if !output.status.success() {
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                if line.contains(" error on line ") {
+                    actual.push_str(line);
+                    actual.push('\n');
+                }
+            }
+            actual.push_str(&format!("{}\n", output.status));
+        }
+        let expected_path = path.with_extension("expected");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{name} printed:\n{actual}\nbut expected:\n{expected}"
+            )),
+            Err(_) => failures.push(format!(
+                "{name} has no .expected file, run with KRABER_BLESS=1 to create it"
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// runs `kraber test` on a directory with one failing test and compares its report
+// with `runner.expected`, KRABER_BLESS=1 works here as well
+#[test]
+fn test_runner_reports_failures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let output = Command::new(env!("CARGO_BIN_EXE_kraber"))
+        .arg("test")
+        .arg(dir.join("runner"))
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "a failing test must fail the run"
+    );
+    let actual = String::from_utf8_lossy(&output.stdout).to_string();
+    let expected_path = dir.join("runner.expected");
+    if env::var_os("KRABER_BLESS").is_some() {
+        fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&expected_path)
+        .expect("runner.expected is missing, run with KRABER_BLESS=1 to create it");
+    assert_eq!(expected, actual);
+}
+
+// an empty directory of its own for a test that works with files
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("kraber-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("data")).unwrap();
+    dir
+}
+
+// runs `code` as main.kraber inside dir with the given options
+fn run_in(dir: &Path, flags: &[&str], code: &str) -> Output {
+    fs::write(dir.join("main.kraber"), code).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_kraber"))
+        .args(flags)
+        .arg("main.kraber")
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+// the file builtins work inside the allowed directories and nowhere else
+#[test]
+fn sandbox_grants_access_to_allowed_directories() {
+    let dir = temp_dir("files");
+    fs::write(dir.join("data/notes.txt"), "kept for the file examples").unwrap();
+    fs::write(dir.join("secret.txt"), "outside").unwrap();
+    let output = run_in(
+        &dir,
+        &["--allow-read=data", "--allow-write=data"],
+        r#"read_file("data/notes.txt")
+write_file("data/scratch.txt" "first")
+append_file("data/scratch.txt" " second")
+read_file("data/scratch.txt")
+exists("data/scratch.txt")
+list_dir("data")
+delete_file("data/scratch.txt")
+exists("data/scratch.txt")
+try {
+  read_file("data/../secret.txt")
+} catch e {
+  e.message
+}
+try {
+  write_file("secret.txt" "overwritten")
+} catch e {
+  e.message
+}
+"#,
+    );
+    let secret = fs::read_to_string(dir.join("secret.txt")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "kept for the file examples\nfirst second\ntrue\n[\"notes.txt\", \"scratch.txt\"]\nfalse\n\
+         no read access to data/../secret.txt\nno write access to secret.txt\n"
+    );
+    assert_eq!(secret, "outside");
+}
+
+// a link inside an allowed directory must not give access to what it points to,
+// even when its target does not exist yet
+#[cfg(unix)]
+#[test]
+fn sandbox_rejects_links_out_of_allowed_directories() {
+    let dir = temp_dir("links");
+    let outside = dir.join("outside.txt");
+    std::os::unix::fs::symlink(&outside, dir.join("data/link.txt")).unwrap();
+    let output = run_in(
+        &dir,
+        &["--allow-write=data"],
+        "write_file(\"data/link.txt\" \"escaped\")\n",
+    );
+    let escaped = outside.exists();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        !escaped,
+        "wrote through a link out of the allowed directory"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("permission error"));
+}
+
+// deleting a link removes the link and leaves what it points to alone
+#[cfg(unix)]
+#[test]
+fn delete_file_removes_links_themselves() {
+    let dir = temp_dir("unlink");
+    let target = dir.join("target.txt");
+    fs::write(&target, "kept").unwrap();
+    std::os::unix::fs::symlink(&target, dir.join("data/link.txt")).unwrap();
+    let output = run_in(
+        &dir,
+        &["--allow-write=data"],
+        "delete_file(\"data/link.txt\")\n",
+    );
+    let link = fs::symlink_metadata(dir.join("data/link.txt")).is_ok();
+    let kept = target.exists();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!link, "the link was not deleted");
+    assert!(kept, "deleted the target of the link");
+}